@@ -0,0 +1,165 @@
+//! Price discovery for call auctions.
+
+use crate::{report::ExecutionReport, Side, TradeLog};
+
+/// The outcome of uncrossing an auction at the current state of the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuctionIndication {
+    pub price: u64,
+    /// The quantity that would execute at `price`.
+    pub volume: u64,
+    /// Quantity left unmatched at `price` on `imbalance_side`, which is `None` when
+    /// both sides match exactly.
+    pub imbalance: u64,
+    pub imbalance_side: Option<Side>,
+}
+
+/// The trades of an uncrossed auction.
+#[derive(Debug)]
+pub struct UncrossResult {
+    /// The price and volume the auction uncrossed at, or `None` if the book was not
+    /// crossed and nothing traded.
+    pub indication: Option<AuctionIndication>,
+    pub trade_log: TradeLog,
+    /// Ids of stop orders released by the auction trades, in the order they were
+    /// released.
+    pub triggered_orders: Vec<u64>,
+    pub triggered_trade_log: TradeLog,
+    pub reports: Vec<ExecutionReport>,
+}
+
+/// Finds the clearing price of an auction from the total quantity at each bid and
+/// ask price.
+///
+/// The price maximises the executed volume. Ties go to the price leaving the
+/// smallest imbalance, then to market pressure: the highest price if every
+/// remaining candidate has excess demand, the lowest if every one has excess
+/// supply. Anything still tied is settled by the price closest to
+/// `reference_price`, or to the middle of the remaining prices without one, and
+/// finally by the lower price.
+pub(crate) fn equilibrium(
+    bids: &[(u64, u64)],
+    asks: &[(u64, u64)],
+    reference_price: Option<u64>,
+) -> Option<AuctionIndication> {
+    let mut candidates: Vec<(u64, u64, u64)> = bids
+        .iter()
+        .chain(asks)
+        .map(|&(price, _)| {
+            let demand = bids
+                .iter()
+                .filter(|&&(bid_price, _)| bid_price >= price)
+                .map(|&(_, quantity)| quantity)
+                .sum();
+            let supply = asks
+                .iter()
+                .filter(|&&(ask_price, _)| ask_price <= price)
+                .map(|&(_, quantity)| quantity)
+                .sum();
+            (price, demand, supply)
+        })
+        .collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let volume = candidates
+        .iter()
+        .map(|&(_, demand, supply)| demand.min(supply))
+        .max()
+        .filter(|&volume| volume > 0)?;
+    candidates.retain(|&(_, demand, supply)| demand.min(supply) == volume);
+    let imbalance = candidates
+        .iter()
+        .map(|&(_, demand, supply)| demand.abs_diff(supply))
+        .min()?;
+    candidates.retain(|&(_, demand, supply)| demand.abs_diff(supply) == imbalance);
+
+    // candidates are sorted by price
+    let lowest = candidates.first()?.0;
+    let highest = candidates.last()?.0;
+    let (price, demand, supply) = if candidates
+        .iter()
+        .all(|&(_, demand, supply)| demand > supply)
+    {
+        *candidates.last()?
+    } else if candidates
+        .iter()
+        .all(|&(_, demand, supply)| demand < supply)
+    {
+        *candidates.first()?
+    } else {
+        let reference_price = reference_price.unwrap_or(lowest + (highest - lowest) / 2);
+        *candidates
+            .iter()
+            .min_by_key(|&&(price, _, _)| (price.abs_diff(reference_price), price))?
+    };
+
+    Some(AuctionIndication {
+        price,
+        volume,
+        imbalance,
+        imbalance_side: match demand.cmp(&supply) {
+            std::cmp::Ordering::Greater => Some(Side::Bid),
+            std::cmp::Ordering::Less => Some(Side::Ask),
+            std::cmp::Ordering::Equal => None,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Order, OrderType, Orderbook, Trade};
+
+    #[test]
+    fn equilibrium_maximises_volume_then_minimises_imbalance() {
+        let bids = [(102, 10), (100, 5)];
+        let asks = [(99, 8), (101, 6)];
+        // 101 and 102 both execute 10 leaving 4 unsold, so supply pressure picks 101
+        assert_eq!(
+            equilibrium(&bids, &asks, None),
+            Some(AuctionIndication {
+                price: 101,
+                volume: 10,
+                imbalance: 4,
+                imbalance_side: Some(Side::Ask),
+            })
+        );
+    }
+
+    #[test]
+    fn equilibrium_follows_market_pressure() {
+        let indication = equilibrium(&[(101, 10)], &[(99, 4), (100, 2)], None).unwrap();
+        assert_eq!((indication.price, indication.volume), (101, 6));
+        assert_eq!(indication.imbalance_side, Some(Side::Bid));
+    }
+
+    #[test]
+    fn balanced_ties_go_to_the_reference_price() {
+        let (bids, asks) = ([(102, 5)], [(98, 5)]);
+        assert_eq!(equilibrium(&bids, &asks, Some(101)).unwrap().price, 102);
+        // without a reference both prices are as far from the middle, so the lower wins
+        assert_eq!(equilibrium(&bids, &asks, None).unwrap().price, 98);
+        assert_eq!(equilibrium(&[(99, 5)], &[(100, 5)], None), None);
+    }
+
+    #[test]
+    fn indicative_uncross_matches_the_uncross() {
+        let mut book = Orderbook::new();
+        book.start_auction().unwrap();
+        assert_eq!(book.indicative_uncross(), None);
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 101, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 99, 4))
+            .unwrap();
+
+        let indication = book.indicative_uncross().unwrap();
+        assert_eq!((indication.price, indication.volume), (101, 4));
+        assert_eq!(book.order_count(), 2);
+
+        let uncross = book.uncross().unwrap();
+        assert_eq!(uncross.indication, Some(indication));
+        assert_eq!(uncross.trade_log, vec![Trade::new(1, 2, 101, 4)]);
+        assert!(!book.in_auction());
+    }
+}
@@ -0,0 +1,444 @@
+//! Batch replay of captured order flow from CSV or newline-delimited JSON.
+//!
+//! Every record is an `add`, `cancel` or `modify` with the fields
+//!
+//! ```text
+//! action,order_id,side,price,quantity,type,display,post_only,owner
+//! ```
+//!
+//! CSV input starts with a header row naming the columns, in any order. NDJSON input
+//! has one flat object per line using the same names, e.g.
+//! `{"action":"add","order_id":1,"side":"buy","price":100,"quantity":10,"type":"FAK"}`.
+//! Only `action` and `order_id` are needed for a cancel; `type` defaults to `GTC`
+//! and uses the codes from `protocol`, `post_only` is `reject` or `slide`. Absent,
+//! empty and `null` fields are treated alike.
+
+use crate::{
+    matching::MatchingAlgorithm,
+    protocol::{self, Request},
+    ModifyOrder, Order, OrderType, Orderbook, PostOnly, Side,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    iter::Peekable,
+    path::Path,
+    str::Chars,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Csv,
+    Ndjson,
+}
+
+impl InputFormat {
+    /// Picks the format from a file extension: `.csv`, or `.ndjson`, `.jsonl` and `.json`.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "csv" => Some(InputFormat::Csv),
+            "ndjson" | "jsonl" | "json" => Some(InputFormat::Ndjson),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BatchSummary {
+    pub records: u64,
+    pub trades: u64,
+    pub rejects: u64,
+}
+
+type Record = HashMap<String, String>;
+
+/// Applies every record in `input` to `book`, writing each trade to `trades` and
+/// each record that could not be parsed or was refused by the book to `rejects`,
+/// both as CSV.
+pub fn run_batch<M: MatchingAlgorithm>(
+    book: &mut Orderbook<M>,
+    input: impl BufRead,
+    format: InputFormat,
+    mut trades: impl Write,
+    mut rejects: impl Write,
+) -> io::Result<BatchSummary> {
+    writeln!(trades, "trade,taker_order_id,maker_order_id,price,quantity")?;
+    writeln!(rejects, "line,order_id,reason")?;
+
+    let mut summary = BatchSummary::default();
+    let mut header = None;
+    for (line_number, line) in (1..).zip(input.lines()) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match format {
+            InputFormat::Csv => match &header {
+                None => {
+                    header = Some(split_csv(&line).map_err(invalid_data)?);
+                    continue;
+                }
+                Some(header) => split_csv(&line).and_then(|fields| {
+                    if fields.len() > header.len() {
+                        return Err("more fields than the header".to_owned());
+                    }
+                    Ok(header.iter().cloned().zip(fields).collect())
+                }),
+            },
+            InputFormat::Ndjson => parse_json_object(&line),
+        };
+        summary.records += 1;
+
+        let request = match record.and_then(|record| request_from_record(&record)) {
+            Ok(request) => request,
+            Err(err) => {
+                summary.rejects += 1;
+                writeln!(rejects, "{line_number},,{}", escape_csv(&err))?;
+                continue;
+            }
+        };
+        let order_id = request.order_id();
+        let result = match request {
+            Request::Add(order) => book.add_order(order).map(Some),
+            Request::Modify(order) => book.modify_order(order).map(Some),
+            Request::Cancel(order_id) => book.cancel_order(order_id).map(|_| None),
+        };
+        match result {
+            Ok(None) => {}
+            Ok(Some(match_info)) => {
+                for trade in match_info
+                    .trade_log
+                    .iter()
+                    .chain(&match_info.triggered_trade_log)
+                {
+                    summary.trades += 1;
+                    writeln!(
+                        trades,
+                        "{},{},{},{},{}",
+                        summary.trades,
+                        trade.taker_order_id,
+                        trade.maker_order_id,
+                        trade.price,
+                        trade.quantity
+                    )?;
+                }
+            }
+            Err(err) => {
+                summary.rejects += 1;
+                writeln!(
+                    rejects,
+                    "{line_number},{order_id},{}",
+                    escape_csv(&err.to_string())
+                )?;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Writes every resting order as CSV, bids then asks, best price first.
+pub fn write_book<M: MatchingAlgorithm>(
+    book: &Orderbook<M>,
+    mut out: impl Write,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "side,price,queue_position,order_id,remaining_quantity,displayed_quantity"
+    )?;
+    for side in [Side::Bid, Side::Ask] {
+        let side_name = match side {
+            Side::Bid => "buy",
+            Side::Ask => "sell",
+        };
+        for order in book.side_orders(side) {
+            writeln!(
+                out,
+                "{side_name},{},{},{},{},{}",
+                order.price,
+                order.queue_position,
+                order.order_id,
+                order.remaining_quantity,
+                order.displayed_quantity
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn request_from_record(record: &Record) -> Result<Request, String> {
+    let field = |name: &str| {
+        record
+            .get(name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty() && *value != "null")
+    };
+    let number = |name: &str| -> Result<u64, String> {
+        let value = field(name).ok_or_else(|| format!("missing {name}"))?;
+        value.parse().map_err(|_| format!("invalid {name} {value}"))
+    };
+    let side = || match field("side").map(str::to_ascii_lowercase).as_deref() {
+        Some("buy" | "bid" | "b") => Ok(Side::Bid),
+        Some("sell" | "ask" | "s") => Ok(Side::Ask),
+        Some(side) => Err(format!("invalid side {side}")),
+        None => Err("missing side".to_owned()),
+    };
+
+    let order_id = number("order_id")?;
+    let action = field("action")
+        .ok_or("missing action")?
+        .to_ascii_lowercase();
+    match action.as_str() {
+        "add" => {
+            let order_type = match field("type") {
+                None => OrderType::GoodTillCancel,
+                Some(code) => protocol::parse_order_type(code)?
+                    .ok_or_else(|| format!("invalid type {code}"))?,
+            };
+            let mut order = Order::new(
+                order_type,
+                order_id,
+                side()?,
+                number("price")?,
+                number("quantity")?,
+            );
+            if field("display").is_some() {
+                order = order.with_display_quantity(number("display")?);
+            }
+            if field("owner").is_some() {
+                order = order.with_owner(number("owner")?);
+            }
+            match field("post_only").map(str::to_ascii_lowercase).as_deref() {
+                None | Some("false") => {}
+                Some("reject" | "true") => order = order.with_post_only(PostOnly::Reject),
+                Some("slide") => order = order.with_post_only(PostOnly::Slide),
+                Some(mode) => return Err(format!("invalid post_only {mode}")),
+            }
+            Ok(Request::Add(order))
+        }
+        "cancel" => Ok(Request::Cancel(order_id)),
+        "modify" => Ok(Request::Modify(ModifyOrder::new(
+            order_id,
+            side()?,
+            number("price")?,
+            number("quantity")?,
+        ))),
+        _ => Err(format!("invalid action {action}")),
+    }
+}
+
+fn split_csv(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            (false, ',') => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("unterminated quoted field".to_owned());
+    }
+    fields.push(field.trim().to_owned());
+    Ok(fields)
+}
+
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Parses a flat JSON object whose values are strings, numbers, booleans or null,
+/// keeping every value as text.
+fn parse_json_object(line: &str) -> Result<Record, String> {
+    let mut chars = line.trim().chars().peekable();
+    let mut record = Record::new();
+
+    expect(&mut chars, '{')?;
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    if chars.next_if_eq(&'}').is_some() {
+        return Ok(record);
+    }
+    loop {
+        expect(&mut chars, '"')?;
+        let key = parse_json_string(&mut chars)?;
+        expect(&mut chars, ':')?;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let value = if chars.next_if_eq(&'"').is_some() {
+            parse_json_string(&mut chars)?
+        } else {
+            let mut token = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "+-.".contains(*c)) {
+                token.push(c);
+            }
+            if token.is_empty() {
+                return Err("expected a string, number, boolean or null".to_owned());
+            }
+            token
+        };
+        record.insert(key, value);
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => break,
+            _ => return Err("expected ',' or '}'".to_owned()),
+        }
+    }
+    if chars.any(|c| !c.is_whitespace()) {
+        return Err("trailing characters after object".to_owned());
+    }
+    Ok(record)
+}
+
+fn expect(chars: &mut Peekable<Chars>, want: char) -> Result<(), String> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    match chars.next() {
+        Some(c) if c == want => Ok(()),
+        Some(c) => Err(format!("expected '{want}' but found '{c}'")),
+        None => Err(format!("expected '{want}'")),
+    }
+}
+
+/// Reads the rest of a JSON string whose opening quote has been consumed.
+fn parse_json_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(value),
+            '\\' => match chars.next().ok_or("unterminated string")? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'r' => value.push('\r'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or("invalid unicode escape")?;
+                    value.push(c);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+fn invalid_data(err: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(input: &str, format: InputFormat) -> (BatchSummary, String, String, Orderbook) {
+        let mut book = Orderbook::new();
+        let (mut trades, mut rejects) = (Vec::new(), Vec::new());
+        let summary = run_batch(
+            &mut book,
+            input.as_bytes(),
+            format,
+            &mut trades,
+            &mut rejects,
+        )
+        .unwrap();
+        (
+            summary,
+            String::from_utf8(trades).unwrap(),
+            String::from_utf8(rejects).unwrap(),
+            book,
+        )
+    }
+
+    #[test]
+    fn csv_columns_follow_the_header() {
+        let input = "\
+quantity,price,side,order_id,action,display
+10,100,sell,1,add,4
+
+4,100,buy,2,add,
+,,,1,cancel,
+6,100,buy,3,add,
+";
+        let (summary, trades, rejects, book) = run(input, InputFormat::Csv);
+        assert_eq!(
+            (summary.records, summary.trades, summary.rejects),
+            (4, 1, 0)
+        );
+        assert_eq!(
+            trades,
+            "trade,taker_order_id,maker_order_id,price,quantity\n1,2,1,100,4\n"
+        );
+        assert_eq!(rejects, "line,order_id,reason\n");
+        assert_eq!(book.best_bid(), Some(100));
+        assert_eq!(book.best_ask(), None);
+    }
+
+    #[test]
+    fn ndjson_records_are_parsed() {
+        let input = r#"
+{"action":"add","order_id":1,"side":"sell","price":100,"quantity":10,"owner":null}
+{ "action" : "add", "order_id" : 2, "side" : "buy", "price" : 100, "quantity" : 15, "type" : "FAK" }
+{"action":"add","order_id":3,"side":"sell","price":101,"quantity":5,"post_only":"slide"}
+{"action":"modify","order_id":3,"side":"sell","price":102,"quantity":5}
+"#;
+        let (summary, trades, _, book) = run(input, InputFormat::Ndjson);
+        assert_eq!(
+            (summary.records, summary.trades, summary.rejects),
+            (4, 1, 0)
+        );
+        assert!(trades.ends_with("1,2,1,100,10\n"));
+        assert_eq!(book.best_ask(), Some(102));
+        assert_eq!(book.best_bid(), None);
+    }
+
+    #[test]
+    fn bad_records_are_reported_without_stopping_the_batch() {
+        let input = r#"
+{"action":"add","order_id":1,"side":"sell","price":100}
+{"action":"add","order_id":2,"side":"up","price":100,"quantity":1}
+{"action":"add","order_id":3
+{"action":"cancel","order_id":9}
+{"action":"add","order_id":4,"side":"buy","price":99,"quantity":1}
+"#;
+        let (summary, _, rejects, book) = run(input, InputFormat::Ndjson);
+        assert_eq!((summary.records, summary.rejects), (5, 4));
+        let lines: Vec<_> = rejects.lines().skip(1).collect();
+        assert_eq!(lines[0], "2,,missing quantity");
+        assert_eq!(lines[1], "3,,invalid side up");
+        assert!(lines[2].starts_with("4,,"));
+        assert!(lines[3].starts_with("5,9,"));
+        assert_eq!(book.best_bid(), Some(99));
+    }
+
+    #[test]
+    fn quoted_csv_fields() {
+        assert_eq!(
+            split_csv(r#"a, "b,c" ,"say ""hi""""#).unwrap(),
+            ["a", "b,c", r#"say "hi""#]
+        );
+        assert!(split_csv(r#"a,"b"#).is_err());
+        assert_eq!(escape_csv("a,b"), r#""a,b""#);
+        assert_eq!(
+            InputFormat::from_path("flow.JSONL"),
+            Some(InputFormat::Ndjson)
+        );
+        assert_eq!(InputFormat::from_path("flow.txt"), None);
+    }
+}
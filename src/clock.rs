@@ -0,0 +1,56 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Nanoseconds since the unix epoch.
+pub type Timestamp = u64;
+
+/// Source of time for an `Orderbook`, used to decide when orders expire.
+pub trait Clock: Send {
+    fn now(&self) -> Timestamp;
+}
+
+/// Reads the system wall clock.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as Timestamp)
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a handle
+/// can be kept to drive an `Orderbook` that owns another clone.
+#[derive(Debug, Default, Clone)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> Self {
+        Self {
+            now: Arc::new(AtomicU64::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, nanos: u64) {
+        self.now.fetch_add(nanos, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::SeqCst)
+    }
+}
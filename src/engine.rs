@@ -0,0 +1,349 @@
+use crate::{
+    matching::{MatchingAlgorithm, PriceTimePriority},
+    ExpiredOrder, LivreError, MatchInfo, ModifyOrder, Order, Orderbook,
+};
+use std::{collections::HashMap, ops::AddAssign};
+
+/// Activity counters for one instrument, or for the whole engine when aggregated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InstrumentStats {
+    pub orders_accepted: u64,
+    pub orders_rejected: u64,
+    pub orders_cancelled: u64,
+    pub orders_modified: u64,
+    pub trade_count: u64,
+    pub volume: u64,
+    pub notional: u128,
+    pub resting_orders: usize,
+}
+
+impl InstrumentStats {
+    fn record_trades(&mut self, match_info: &MatchInfo) {
+        for trade in match_info
+            .trade_log
+            .iter()
+            .chain(&match_info.triggered_trade_log)
+        {
+            self.trade_count += 1;
+            self.volume += trade.quantity;
+            self.notional += trade.price as u128 * trade.quantity as u128;
+        }
+    }
+}
+
+impl AddAssign for InstrumentStats {
+    fn add_assign(&mut self, other: Self) {
+        self.orders_accepted += other.orders_accepted;
+        self.orders_rejected += other.orders_rejected;
+        self.orders_cancelled += other.orders_cancelled;
+        self.orders_modified += other.orders_modified;
+        self.trade_count += other.trade_count;
+        self.volume += other.volume;
+        self.notional += other.notional;
+        self.resting_orders += other.resting_orders;
+    }
+}
+
+struct Instrument<M> {
+    book: Orderbook<M>,
+    stats: InstrumentStats,
+}
+
+/// Owns one `Orderbook` per instrument and routes commands to them by symbol.
+/// Order ids are unique across every instrument for the lifetime of the engine.
+pub struct MatchingEngine<M = PriceTimePriority> {
+    instruments: HashMap<String, Instrument<M>>,
+    order_ids: HashMap<u64, String>,
+}
+
+impl<M: MatchingAlgorithm> MatchingEngine<M> {
+    pub fn new() -> Self {
+        Self {
+            instruments: HashMap::new(),
+            order_ids: HashMap::new(),
+        }
+    }
+
+    /// Adds the book of a new instrument. Orders already in the book, including
+    /// stop orders, are routed to it like orders entered through the engine, so the
+    /// book is rejected with `DuplicateOrderId` if one of their ids is in use.
+    pub fn add_instrument(&mut self, symbol: &str, book: Orderbook<M>) -> Result<(), LivreError> {
+        if self.instruments.contains_key(symbol) {
+            return Err(LivreError::DuplicateInstrument);
+        }
+        let order_ids: Vec<u64> = book
+            .orders
+            .keys()
+            .chain(book.stop_orders.keys())
+            .copied()
+            .collect();
+        if order_ids
+            .iter()
+            .any(|order_id| self.order_ids.contains_key(order_id))
+        {
+            return Err(LivreError::DuplicateOrderId);
+        }
+        self.order_ids.extend(
+            order_ids
+                .into_iter()
+                .map(|order_id| (order_id, symbol.to_owned())),
+        );
+        self.instruments.insert(
+            symbol.to_owned(),
+            Instrument {
+                book,
+                stats: InstrumentStats::default(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn add_order(&mut self, symbol: &str, order: Order) -> Result<MatchInfo, LivreError> {
+        let instrument = self
+            .instruments
+            .get_mut(symbol)
+            .ok_or(LivreError::UnknownInstrument)?;
+        if self.order_ids.contains_key(&order.order_id) {
+            instrument.stats.orders_rejected += 1;
+            return Err(LivreError::DuplicateOrderId);
+        }
+
+        let order_id = order.order_id;
+        match instrument.book.add_order(order) {
+            Ok(match_info) => {
+                self.order_ids.insert(order_id, symbol.to_owned());
+                instrument.stats.orders_accepted += 1;
+                instrument.stats.record_trades(&match_info);
+                Ok(match_info)
+            }
+            Err(err) => {
+                instrument.stats.orders_rejected += 1;
+                Err(err)
+            }
+        }
+    }
+
+    pub fn cancel_order(&mut self, symbol: &str, order_id: u64) -> Result<Order, LivreError> {
+        let instrument = self.routed_instrument(symbol, order_id)?;
+        let order = instrument.book.cancel_order(order_id)?;
+        instrument.stats.orders_cancelled += 1;
+        Ok(order)
+    }
+
+    pub fn modify_order(
+        &mut self,
+        symbol: &str,
+        order: ModifyOrder,
+    ) -> Result<MatchInfo, LivreError> {
+        let instrument = self.routed_instrument(symbol, order.order_id)?;
+        let match_info = instrument.book.modify_order(order)?;
+        instrument.stats.orders_modified += 1;
+        instrument.stats.record_trades(&match_info);
+        Ok(match_info)
+    }
+
+    /// Runs `Orderbook::expire_orders` on every book.
+    pub fn expire_orders(&mut self) -> Vec<(String, ExpiredOrder)> {
+        self.instruments
+            .iter_mut()
+            .flat_map(|(symbol, instrument)| {
+                instrument
+                    .book
+                    .expire_orders()
+                    .into_iter()
+                    .map(|expired| (symbol.clone(), expired))
+            })
+            .collect()
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&Orderbook<M>> {
+        self.instruments
+            .get(symbol)
+            .map(|instrument| &instrument.book)
+    }
+
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.instruments.keys().map(String::as_str)
+    }
+
+    pub fn stats(&self, symbol: &str) -> Option<InstrumentStats> {
+        self.instruments
+            .get(symbol)
+            .map(|instrument| InstrumentStats {
+                resting_orders: instrument.book.order_count(),
+                ..instrument.stats
+            })
+    }
+
+    pub fn aggregate_stats(&self) -> InstrumentStats {
+        let mut total = InstrumentStats::default();
+        for symbol in self.instruments.keys() {
+            // every key has an instrument, so the stats exist
+            total += self.stats(symbol).unwrap();
+        }
+        total
+    }
+
+    fn routed_instrument(
+        &mut self,
+        symbol: &str,
+        order_id: u64,
+    ) -> Result<&mut Instrument<M>, LivreError> {
+        let instrument = self
+            .instruments
+            .get_mut(symbol)
+            .ok_or(LivreError::UnknownInstrument)?;
+        match self.order_ids.get(&order_id) {
+            Some(order_symbol) if order_symbol == symbol => Ok(instrument),
+            _ => Err(LivreError::OrderNotFound),
+        }
+    }
+}
+
+impl<M: MatchingAlgorithm> Default for MatchingEngine<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OrderType, Side};
+
+    fn engine() -> MatchingEngine {
+        let mut engine = MatchingEngine::new();
+        engine.add_instrument("AAPL", Orderbook::new()).unwrap();
+        engine.add_instrument("MSFT", Orderbook::new()).unwrap();
+        engine
+    }
+
+    #[test]
+    fn orders_are_routed_to_their_instrument() {
+        let mut engine = engine();
+        engine
+            .add_order(
+                "AAPL",
+                Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10),
+            )
+            .unwrap();
+        let match_info = engine
+            .add_order(
+                "MSFT",
+                Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 10),
+            )
+            .unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(engine.book("AAPL").unwrap().best_ask(), Some(100));
+        assert_eq!(engine.book("MSFT").unwrap().best_bid(), Some(100));
+        let order = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 1, 1);
+        assert!(matches!(
+            engine.add_order("TSLA", order),
+            Err(LivreError::UnknownInstrument)
+        ));
+        assert!(matches!(
+            engine.add_instrument("AAPL", Orderbook::new()),
+            Err(LivreError::DuplicateInstrument)
+        ));
+    }
+
+    #[test]
+    fn order_ids_are_unique_across_instruments() {
+        let mut engine = engine();
+        engine
+            .add_order(
+                "AAPL",
+                Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10),
+            )
+            .unwrap();
+        assert!(matches!(
+            engine.add_order(
+                "MSFT",
+                Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10)
+            ),
+            Err(LivreError::DuplicateOrderId)
+        ));
+        // orders can only be cancelled through the instrument they were sent to
+        assert!(matches!(
+            engine.cancel_order("MSFT", 1),
+            Err(LivreError::OrderNotFound)
+        ));
+        engine.cancel_order("AAPL", 1).unwrap();
+    }
+
+    #[test]
+    fn orders_of_added_books_are_routed_and_keep_their_ids() {
+        let mut engine = engine();
+        engine
+            .add_order(
+                "AAPL",
+                Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10),
+            )
+            .unwrap();
+
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 90, 5))
+            .unwrap();
+        assert!(matches!(
+            engine.add_instrument("TSLA", book),
+            Err(LivreError::DuplicateOrderId)
+        ));
+        assert_eq!(engine.symbols().count(), 2);
+
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 90, 5))
+            .unwrap();
+        book.add_order(Order::new(
+            OrderType::StopMarket { stop_price: 95 },
+            3,
+            Side::Bid,
+            0,
+            1,
+        ))
+        .unwrap();
+        engine.add_instrument("TSLA", book).unwrap();
+        for order_id in [2, 3] {
+            let order = Order::new(OrderType::GoodTillCancel, order_id, Side::Ask, 100, 1);
+            assert!(matches!(
+                engine.add_order("AAPL", order),
+                Err(LivreError::DuplicateOrderId)
+            ));
+        }
+        engine.cancel_order("TSLA", 3).unwrap();
+        engine.cancel_order("TSLA", 2).unwrap();
+        assert_eq!(engine.book("TSLA").unwrap().order_count(), 0);
+    }
+
+    #[test]
+    fn stats_count_activity_per_instrument_and_in_total() {
+        let mut engine = engine();
+        engine
+            .add_order(
+                "AAPL",
+                Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10),
+            )
+            .unwrap();
+        engine
+            .add_order(
+                "AAPL",
+                Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 4),
+            )
+            .unwrap();
+        engine
+            .add_order(
+                "MSFT",
+                Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 50, 1),
+            )
+            .unwrap();
+        let _ = engine.add_order("MSFT", Order::new(OrderType::Market, 4, Side::Bid, 0, 1));
+
+        let aapl = engine.stats("AAPL").unwrap();
+        assert_eq!(aapl.orders_accepted, 2);
+        assert_eq!((aapl.trade_count, aapl.volume, aapl.notional), (1, 4, 400));
+        assert_eq!(aapl.resting_orders, 1);
+        let total = engine.aggregate_stats();
+        assert_eq!(total.orders_accepted, 3);
+        assert_eq!(total.orders_rejected, 1);
+        assert_eq!(total.resting_orders, 2);
+    }
+}
@@ -0,0 +1,399 @@
+//! FIX 4.4 order entry: a tag-value codec and a session layer that maps order
+//! messages onto an `Orderbook`.
+
+mod session;
+
+pub use session::{FixSession, SessionState};
+
+use crate::clock::Timestamp;
+use std::{error::Error, fmt::Display};
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+pub const SOH: u8 = 0x01;
+
+pub mod tags {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const EXEC_INST: u32 = 18;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const STOP_PX: u32 = 99;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const MAX_FLOOR: u32 = 111;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const EXPIRE_TIME: u32 = 126;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FixError {
+    /// The bytes do not form a valid tag-value message.
+    Malformed(&'static str),
+    BadBeginString,
+    BadBodyLength,
+    BadCheckSum,
+}
+
+impl Display for FixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FixError::Malformed(reason) => write!(f, "malformed FIX message: {reason}"),
+            FixError::BadBeginString => f.write_str("unsupported FIX version"),
+            FixError::BadBodyLength => f.write_str("body length does not match message"),
+            FixError::BadCheckSum => f.write_str("checksum does not match message"),
+        }
+    }
+}
+
+impl Error for FixError {}
+
+/// A FIX message without its framing fields (BeginString, BodyLength and CheckSum),
+/// which are added by `encode` and checked by `decode`. Fields keep their order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            fields: vec![(tags::MSG_TYPE, msg_type.to_owned())],
+        }
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tags::MSG_TYPE).unwrap_or_default()
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field_tag, _)| *field_tag == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn get_u64(&self, tag: u32) -> Option<u64> {
+        self.get(tag)?.parse().ok()
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.fields
+            .iter()
+            .map(|(tag, value)| (*tag, value.as_str()))
+    }
+
+    /// Sets a field, replacing its value if the message already has it.
+    pub fn set(&mut self, tag: u32, value: impl ToString) -> &mut Self {
+        let value = value.to_string();
+        match self
+            .fields
+            .iter_mut()
+            .find(|(field_tag, _)| *field_tag == tag)
+        {
+            Some(field) => field.1 = value,
+            None => self.fields.push((tag, value)),
+        }
+        self
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.set(tag, value);
+        self
+    }
+
+    /// Serialises the message with its framing fields.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{tag}={value}").as_bytes());
+            body.push(SOH);
+        }
+
+        let mut message = format!("8={BEGIN_STRING}\x019={}\x01", body.len()).into_bytes();
+        message.extend_from_slice(&body);
+        let check_sum = checksum(&message);
+        message.extend_from_slice(format!("10={check_sum:03}\x01").as_bytes());
+        message
+    }
+
+    /// Decodes the first message in `buffer`, returning it with the number of bytes
+    /// it used, or `None` if the buffer does not hold a complete message yet.
+    pub fn decode(buffer: &[u8]) -> Result<Option<(Self, usize)>, FixError> {
+        let Some((begin_string, rest)) = next_field(buffer, 0)? else {
+            return Ok(None);
+        };
+        if begin_string != (tags::BEGIN_STRING, BEGIN_STRING) {
+            return Err(FixError::BadBeginString);
+        }
+        let Some(((tag, body_length), body_start)) = next_field(buffer, rest)? else {
+            return Ok(None);
+        };
+        if tag != tags::BODY_LENGTH {
+            return Err(FixError::Malformed("BodyLength must be the second field"));
+        }
+        let body_length: usize = body_length
+            .parse()
+            .map_err(|_| FixError::Malformed("invalid BodyLength"))?;
+        let body_end = body_start
+            .checked_add(body_length)
+            .ok_or(FixError::BadBodyLength)?;
+        if buffer.len() < body_end {
+            return Ok(None);
+        }
+
+        let mut fields = Vec::new();
+        let mut position = body_start;
+        while position < body_end {
+            // the body is complete, so a missing delimiter means the length is wrong
+            let ((tag, value), next) =
+                next_field(&buffer[..body_end], position)?.ok_or(FixError::BadBodyLength)?;
+            fields.push((tag, value.to_owned()));
+            position = next;
+        }
+
+        let Some(((tag, check_sum), end)) = next_field(buffer, body_end)? else {
+            return Ok(None);
+        };
+        if tag != tags::CHECK_SUM {
+            return Err(FixError::BadBodyLength);
+        }
+        if check_sum.parse::<u8>().ok() != Some(checksum(&buffer[..body_end])) {
+            return Err(FixError::BadCheckSum);
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(tags::MSG_TYPE) {
+            return Err(FixError::Malformed("MsgType must be the first body field"));
+        }
+        Ok(Some((Self { fields }, end)))
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |check_sum, &byte| check_sum.wrapping_add(byte))
+}
+
+/// Reads the `tag=value` field starting at `start`, returning it with the position
+/// after its delimiter.
+#[allow(clippy::type_complexity)]
+fn next_field(buffer: &[u8], start: usize) -> Result<Option<((u32, &str), usize)>, FixError> {
+    let Some(length) = buffer[start..].iter().position(|&byte| byte == SOH) else {
+        return Ok(None);
+    };
+    let field = std::str::from_utf8(&buffer[start..start + length])
+        .map_err(|_| FixError::Malformed("field is not valid UTF-8"))?;
+    let (tag, value) = field
+        .split_once('=')
+        .ok_or(FixError::Malformed("field without '='"))?;
+    let tag = tag
+        .parse()
+        .map_err(|_| FixError::Malformed("tag is not a number"))?;
+    Ok(Some(((tag, value), start + length + 1)))
+}
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Formats a timestamp as a FIX UTCTimestamp with millisecond precision.
+pub fn format_utc_timestamp(timestamp: Timestamp) -> String {
+    let seconds = timestamp / NANOS_PER_SECOND;
+    let millis = timestamp % NANOS_PER_SECOND / 1_000_000;
+    let (year, month, day) = civil_from_days((seconds / SECONDS_PER_DAY) as i64);
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}:{:02}:{:02}.{millis:03}",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Parses a FIX UTCTimestamp, with or without fractional seconds.
+pub fn parse_utc_timestamp(value: &str) -> Option<Timestamp> {
+    let (date, time) = value.split_once('-')?;
+    if date.len() != 8 || !date.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = date[..4].parse().ok()?;
+    let month: u32 = date[4..6].parse().ok()?;
+    let day: u32 = date[6..].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    let mut nanos = 0;
+    if !fraction.is_empty() {
+        if fraction.len() > 9 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        nanos = fraction.parse::<u64>().ok()? * 10u64.pow(9 - fraction.len() as u32);
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    // timestamps from about the year 2554 on do not fit in a u64
+    let seconds = days
+        .checked_mul(SECONDS_PER_DAY)?
+        .checked_add(hours * 3600 + minutes * 60 + seconds)?;
+    seconds.checked_mul(NANOS_PER_SECOND)?.checked_add(nanos)
+}
+
+// Conversions between days since the unix epoch and the proleptic Gregorian
+// calendar, from Howard Hinnant's date algorithms.
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let message = FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+            .with(tags::CL_ORD_ID, "A1")
+            .with(tags::SIDE, 1)
+            .with(tags::ORDER_QTY, 10);
+        let mut bytes = message.encode();
+        let length = bytes.len();
+        bytes.extend_from_slice(b"8=FIX");
+
+        let (decoded, used) = FixMessage::decode(&bytes).unwrap().unwrap();
+        assert_eq!(decoded, message);
+        assert_eq!(used, length);
+        assert_eq!(decoded.get_u64(tags::ORDER_QTY), Some(10));
+    }
+
+    #[test]
+    fn decode_waits_for_a_complete_message() {
+        let bytes = FixMessage::new(msg_type::HEARTBEAT).encode();
+        for end in 0..bytes.len() {
+            assert_eq!(FixMessage::decode(&bytes[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn decode_rejects_a_bad_checksum() {
+        let mut bytes = FixMessage::new(msg_type::HEARTBEAT).encode();
+        let check_sum_start = bytes.len() - 4;
+        bytes[check_sum_start] = if bytes[check_sum_start] == b'0' {
+            b'1'
+        } else {
+            b'0'
+        };
+        assert_eq!(FixMessage::decode(&bytes), Err(FixError::BadCheckSum));
+    }
+
+    #[test]
+    fn decode_rejects_an_unsupported_version() {
+        assert_eq!(
+            FixMessage::decode(b"8=FIX.4.2\x019=5\x0135=0\x0110=000\x01"),
+            Err(FixError::BadBeginString)
+        );
+    }
+
+    #[test]
+    fn decode_rejects_an_overflowing_body_length() {
+        assert_eq!(
+            FixMessage::decode(b"8=FIX.4.4\x019=18446744073709551615\x0135=0\x01"),
+            Err(FixError::BadBodyLength)
+        );
+    }
+
+    #[test]
+    fn utc_timestamps_round_trip() {
+        let timestamp = 1_700_000_000_123_000_000;
+        let formatted = format_utc_timestamp(timestamp);
+        assert_eq!(formatted, "20231114-22:13:20.123");
+        assert_eq!(parse_utc_timestamp(&formatted), Some(timestamp));
+        assert_eq!(
+            parse_utc_timestamp("20231114-22:13:20"),
+            Some(1_700_000_000_000_000_000)
+        );
+        assert_eq!(parse_utc_timestamp("20231314-22:13:20"), None);
+        assert_eq!(parse_utc_timestamp("99991231-23:59:59"), None);
+    }
+}
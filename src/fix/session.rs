@@ -0,0 +1,1062 @@
+use super::{format_utc_timestamp, msg_type, parse_utc_timestamp, tags, FixMessage};
+use crate::{
+    clock::{Clock, SystemClock, Timestamp},
+    matching::MatchingAlgorithm,
+    ExpiredOrder, LivreError, MatchInfo, ModifyOrder, Order, OrderType, Orderbook, PostOnly,
+    SelfTradePrevention, Side, Trade,
+};
+use std::collections::{BTreeMap, HashMap};
+
+const NANOS_PER_SECOND: u64 = 1_000_000_000;
+
+const HEADER_TAGS: [u32; 5] = [
+    tags::MSG_TYPE,
+    tags::SENDER_COMP_ID,
+    tags::TARGET_COMP_ID,
+    tags::MSG_SEQ_NUM,
+    tags::SENDING_TIME,
+];
+
+// SessionRejectReason values
+const REQUIRED_TAG_MISSING: u32 = 1;
+const VALUE_IS_INCORRECT: u32 = 5;
+const INVALID_MSG_TYPE: u32 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    AwaitingLogon,
+    Active,
+    LoggedOut,
+}
+
+/// What the session knows about an order it accepted.
+struct SessionOrder {
+    cl_ord_id: String,
+    symbol: String,
+    side: Side,
+    order_qty: u64,
+    cum_qty: u64,
+    notional: u128,
+    done: bool,
+}
+
+impl SessionOrder {
+    fn leaves_qty(&self) -> u64 {
+        if self.done {
+            0
+        } else {
+            self.order_qty.saturating_sub(self.cum_qty)
+        }
+    }
+
+    fn avg_px(&self) -> u64 {
+        if self.cum_qty == 0 {
+            0
+        } else {
+            (self.notional / self.cum_qty as u128) as u64
+        }
+    }
+
+    fn ord_status(&self) -> &'static str {
+        if self.cum_qty >= self.order_qty {
+            "2"
+        } else if self.done {
+            "4"
+        } else if self.cum_qty > 0 {
+            "1"
+        } else {
+            "0"
+        }
+    }
+}
+
+/// The acceptor side of a FIX 4.4 session. Incoming messages are handed to
+/// `on_message` together with the book they trade on, and every message the session
+/// wants to send back is returned, ready to be encoded.
+///
+/// ClOrdIDs are mapped to order ids allocated by the session, starting from the
+/// value given to `set_next_order_id`, and Accounts to order owners allocated from
+/// `set_next_owner_id` the first time each one is seen. Sessions sharing a book
+/// must be given disjoint ranges of both.
+pub struct FixSession {
+    sender_comp_id: String,
+    target_comp_id: String,
+    state: SessionState,
+    heartbeat_interval: u64,
+    next_outgoing_seq: u64,
+    next_incoming_seq: u64,
+    // the sequence number that revealed a gap we asked the counterparty to fill
+    resend_until: Option<u64>,
+    last_sent: Timestamp,
+    last_received: Timestamp,
+    test_request_pending: bool,
+    sent: BTreeMap<u64, FixMessage>,
+    cl_ord_ids: HashMap<String, u64>,
+    orders: HashMap<u64, SessionOrder>,
+    next_order_id: u64,
+    accounts: HashMap<String, u64>,
+    next_owner_id: u64,
+    next_exec_id: u64,
+    clock: Box<dyn Clock>,
+}
+
+impl FixSession {
+    pub fn new(sender_comp_id: &str, target_comp_id: &str) -> Self {
+        Self {
+            sender_comp_id: sender_comp_id.to_owned(),
+            target_comp_id: target_comp_id.to_owned(),
+            state: SessionState::AwaitingLogon,
+            heartbeat_interval: 30,
+            next_outgoing_seq: 1,
+            next_incoming_seq: 1,
+            resend_until: None,
+            last_sent: 0,
+            last_received: 0,
+            test_request_pending: false,
+            sent: BTreeMap::new(),
+            cl_ord_ids: HashMap::new(),
+            orders: HashMap::new(),
+            next_order_id: 1,
+            accounts: HashMap::new(),
+            next_owner_id: 1,
+            next_exec_id: 1,
+            clock: Box::new(SystemClock),
+        }
+    }
+
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    pub fn set_next_order_id(&mut self, order_id: u64) {
+        self.next_order_id = order_id;
+    }
+
+    pub fn set_next_owner_id(&mut self, owner: u64) {
+        self.next_owner_id = owner;
+    }
+
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    pub fn next_outgoing_seq(&self) -> u64 {
+        self.next_outgoing_seq
+    }
+
+    pub fn next_incoming_seq(&self) -> u64 {
+        self.next_incoming_seq
+    }
+
+    /// The order id the session allocated for a ClOrdID.
+    pub fn order_id(&self, cl_ord_id: &str) -> Option<u64> {
+        self.cl_ord_ids.get(cl_ord_id).copied()
+    }
+
+    /// The owner id the session allocated for an Account.
+    pub fn owner_id(&self, account: &str) -> Option<u64> {
+        self.accounts.get(account).copied()
+    }
+
+    pub fn on_message<M: MatchingAlgorithm>(
+        &mut self,
+        message: &FixMessage,
+        book: &mut Orderbook<M>,
+    ) -> Vec<FixMessage> {
+        let mut outgoing = Vec::new();
+        if self.state == SessionState::LoggedOut {
+            return outgoing;
+        }
+        self.last_received = self.clock.now();
+        self.test_request_pending = false;
+
+        let msg_type = message.msg_type().to_owned();
+        let Some(seq_num) = message.get_u64(tags::MSG_SEQ_NUM) else {
+            self.logout("MsgSeqNum missing", &mut outgoing);
+            return outgoing;
+        };
+        if message.get(tags::SENDER_COMP_ID) != Some(self.target_comp_id.as_str())
+            || message.get(tags::TARGET_COMP_ID) != Some(self.sender_comp_id.as_str())
+        {
+            self.logout("CompID problem", &mut outgoing);
+            return outgoing;
+        }
+        if self.state == SessionState::AwaitingLogon && msg_type != msg_type::LOGON {
+            self.logout("first message must be Logon", &mut outgoing);
+            return outgoing;
+        }
+
+        if msg_type == msg_type::LOGON && message.get(tags::RESET_SEQ_NUM_FLAG) == Some("Y") {
+            self.next_incoming_seq = 1;
+            self.next_outgoing_seq = 1;
+            self.sent.clear();
+        }
+        let gap_fill = message.get(tags::GAP_FILL_FLAG) == Some("Y");
+        if msg_type == msg_type::SEQUENCE_RESET && !gap_fill {
+            // a reset ignores the sequence number it arrives with
+            match message.get_u64(tags::NEW_SEQ_NO) {
+                Some(new_seq_no) if new_seq_no >= self.next_incoming_seq => {
+                    self.next_incoming_seq = new_seq_no;
+                    self.resend_until = None;
+                }
+                _ => self.reject(message, tags::NEW_SEQ_NO, VALUE_IS_INCORRECT, &mut outgoing),
+            }
+            return outgoing;
+        }
+
+        if seq_num < self.next_incoming_seq {
+            if message.get(tags::POSS_DUP_FLAG) != Some("Y") {
+                let text = format!(
+                    "MsgSeqNum too low, expecting {} but received {seq_num}",
+                    self.next_incoming_seq
+                );
+                self.logout(&text, &mut outgoing);
+            }
+            return outgoing;
+        }
+        if seq_num > self.next_incoming_seq {
+            if msg_type == msg_type::LOGON {
+                self.on_logon(message, &mut outgoing);
+            }
+            if self.resend_until.is_none() {
+                self.resend_until = Some(seq_num);
+                let resend_request = FixMessage::new(msg_type::RESEND_REQUEST)
+                    .with(tags::BEGIN_SEQ_NO, self.next_incoming_seq)
+                    .with(tags::END_SEQ_NO, 0);
+                self.send(resend_request, &mut outgoing);
+            }
+            return outgoing;
+        }
+        self.next_incoming_seq = seq_num + 1;
+
+        match msg_type.as_str() {
+            msg_type::LOGON => self.on_logon(message, &mut outgoing),
+            msg_type::HEARTBEAT | msg_type::REJECT => {}
+            msg_type::TEST_REQUEST => {
+                let mut heartbeat = FixMessage::new(msg_type::HEARTBEAT);
+                if let Some(test_req_id) = message.get(tags::TEST_REQ_ID) {
+                    heartbeat.set(tags::TEST_REQ_ID, test_req_id);
+                }
+                self.send(heartbeat, &mut outgoing);
+            }
+            msg_type::RESEND_REQUEST => self.on_resend_request(message, &mut outgoing),
+            msg_type::SEQUENCE_RESET => match message.get_u64(tags::NEW_SEQ_NO) {
+                Some(new_seq_no) if new_seq_no >= self.next_incoming_seq => {
+                    self.next_incoming_seq = new_seq_no;
+                }
+                _ => self.reject(message, tags::NEW_SEQ_NO, VALUE_IS_INCORRECT, &mut outgoing),
+            },
+            msg_type::LOGOUT => {
+                self.send(FixMessage::new(msg_type::LOGOUT), &mut outgoing);
+                self.state = SessionState::LoggedOut;
+            }
+            msg_type::NEW_ORDER_SINGLE => self.on_new_order_single(message, book, &mut outgoing),
+            msg_type::ORDER_CANCEL_REQUEST => self.on_cancel_request(message, book, &mut outgoing),
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => {
+                self.on_cancel_replace_request(message, book, &mut outgoing)
+            }
+            _ => self.reject(message, tags::MSG_TYPE, INVALID_MSG_TYPE, &mut outgoing),
+        }
+        if self
+            .resend_until
+            .is_some_and(|resend_until| self.next_incoming_seq > resend_until)
+        {
+            self.resend_until = None;
+        }
+        outgoing
+    }
+
+    /// Sends heartbeats and test requests when the session has been quiet for a
+    /// heartbeat interval, and logs out if the counterparty stopped responding.
+    pub fn on_timer(&mut self) -> Vec<FixMessage> {
+        let mut outgoing = Vec::new();
+        if self.state != SessionState::Active {
+            return outgoing;
+        }
+        let now = self.clock.now();
+        let interval = self.heartbeat_interval * NANOS_PER_SECOND;
+        let silence = now.saturating_sub(self.last_received);
+
+        if self.test_request_pending && silence >= 2 * interval {
+            self.logout("heartbeat timeout", &mut outgoing);
+            return outgoing;
+        }
+        if !self.test_request_pending && silence >= interval + interval / 5 {
+            self.test_request_pending = true;
+            let test_request = FixMessage::new(msg_type::TEST_REQUEST)
+                .with(tags::TEST_REQ_ID, format_utc_timestamp(now));
+            self.send(test_request, &mut outgoing);
+        } else if now.saturating_sub(self.last_sent) >= interval {
+            self.send(FixMessage::new(msg_type::HEARTBEAT), &mut outgoing);
+        }
+        outgoing
+    }
+
+    /// Ends the session from our side.
+    pub fn logout(&mut self, text: &str, outgoing: &mut Vec<FixMessage>) {
+        let logout = FixMessage::new(msg_type::LOGOUT).with(tags::TEXT, text);
+        self.send(logout, outgoing);
+        self.state = SessionState::LoggedOut;
+    }
+
+    /// Builds execution reports for trades that happened outside of this session's
+    /// own requests, such as another session's order taking this session's resting
+    /// orders. Trades of orders this session doesn't know are ignored.
+    pub fn report_trades(&mut self, trades: &[Trade]) -> Vec<FixMessage> {
+        let mut outgoing = Vec::new();
+        for trade in trades {
+            for order_id in [trade.taker_order_id, trade.maker_order_id] {
+                self.report_fill(order_id, trade, &mut outgoing);
+            }
+        }
+        outgoing
+    }
+
+    /// Builds execution reports for orders of this session that expired.
+    pub fn report_expired(&mut self, expired: &[ExpiredOrder]) -> Vec<FixMessage> {
+        let mut outgoing = Vec::new();
+        for expired in expired {
+            let order_id = expired.order.order_id;
+            if let Some(order) = self.orders.get_mut(&order_id) {
+                order.done = true;
+                let report = self.execution_report(order_id, "C", "C");
+                self.send(report, &mut outgoing);
+            }
+        }
+        outgoing
+    }
+
+    fn on_logon(&mut self, message: &FixMessage, outgoing: &mut Vec<FixMessage>) {
+        match message.get_u64(tags::HEART_BT_INT) {
+            Some(interval) if interval > 0 => self.heartbeat_interval = interval,
+            _ => {
+                self.logout("invalid HeartBtInt", outgoing);
+                return;
+            }
+        }
+        self.state = SessionState::Active;
+        let mut logon = FixMessage::new(msg_type::LOGON)
+            .with(tags::ENCRYPT_METHOD, 0)
+            .with(tags::HEART_BT_INT, self.heartbeat_interval);
+        if message.get(tags::RESET_SEQ_NUM_FLAG) == Some("Y") {
+            logon.set(tags::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        self.send(logon, outgoing);
+    }
+
+    /// Resends stored application messages, replacing runs of session messages with
+    /// gap fills as required by the protocol.
+    fn on_resend_request(&mut self, message: &FixMessage, outgoing: &mut Vec<FixMessage>) {
+        let (Some(begin), Some(end)) = (
+            message.get_u64(tags::BEGIN_SEQ_NO),
+            message.get_u64(tags::END_SEQ_NO),
+        ) else {
+            self.reject(message, tags::BEGIN_SEQ_NO, REQUIRED_TAG_MISSING, outgoing);
+            return;
+        };
+        let last_sent = self.next_outgoing_seq - 1;
+        let end = if end == 0 {
+            last_sent
+        } else {
+            end.min(last_sent)
+        };
+        if begin == 0 || begin > end {
+            return;
+        }
+
+        let now = format_utc_timestamp(self.clock.now());
+        let mut gap_start = None;
+        for seq_num in begin..=end {
+            let stored = self.sent.get(&seq_num).filter(|stored| {
+                !matches!(
+                    stored.msg_type(),
+                    msg_type::HEARTBEAT
+                        | msg_type::TEST_REQUEST
+                        | msg_type::RESEND_REQUEST
+                        | msg_type::SEQUENCE_RESET
+                        | msg_type::LOGOUT
+                        | msg_type::LOGON
+                )
+            });
+            let Some(stored) = stored else {
+                gap_start.get_or_insert(seq_num);
+                continue;
+            };
+            if let Some(gap_start) = gap_start.take() {
+                outgoing.push(self.gap_fill(gap_start, seq_num, &now));
+            }
+            let original_sending_time = stored.get(tags::SENDING_TIME).unwrap_or_default();
+            let mut resent = self
+                .header(stored.msg_type(), seq_num, &now)
+                .with(tags::POSS_DUP_FLAG, "Y")
+                .with(tags::ORIG_SENDING_TIME, original_sending_time);
+            for (tag, value) in stored.fields() {
+                if !HEADER_TAGS.contains(&tag) {
+                    resent.set(tag, value);
+                }
+            }
+            outgoing.push(resent);
+        }
+        if let Some(gap_start) = gap_start {
+            outgoing.push(self.gap_fill(gap_start, end + 1, &now));
+        }
+    }
+
+    fn gap_fill(&self, seq_num: u64, new_seq_no: u64, sending_time: &str) -> FixMessage {
+        self.header(msg_type::SEQUENCE_RESET, seq_num, sending_time)
+            .with(tags::POSS_DUP_FLAG, "Y")
+            .with(tags::GAP_FILL_FLAG, "Y")
+            .with(tags::NEW_SEQ_NO, new_seq_no)
+    }
+
+    fn on_new_order_single<M: MatchingAlgorithm>(
+        &mut self,
+        message: &FixMessage,
+        book: &mut Orderbook<M>,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        for tag in [tags::CL_ORD_ID, tags::SIDE, tags::ORDER_QTY, tags::ORD_TYPE] {
+            if message.get(tag).is_none() {
+                self.reject(message, tag, REQUIRED_TAG_MISSING, outgoing);
+                return;
+            }
+        }
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default();
+        let symbol = message.get(tags::SYMBOL).unwrap_or_default();
+        if self.cl_ord_ids.contains_key(cl_ord_id) {
+            self.reject_order(message, "duplicate ClOrdID", "6", outgoing);
+            return;
+        }
+        let order_id = self.next_order_id;
+        let order = match self.parse_order(message, order_id) {
+            Ok(order) => order,
+            Err(tag) => {
+                self.reject(message, tag, VALUE_IS_INCORRECT, outgoing);
+                return;
+            }
+        };
+        let side = order.side;
+        let order_qty = order.initial_quantity;
+
+        match book.add_order(order) {
+            Ok(match_info) => {
+                self.next_order_id += 1;
+                self.cl_ord_ids.insert(cl_ord_id.to_owned(), order_id);
+                self.orders.insert(
+                    order_id,
+                    SessionOrder {
+                        cl_ord_id: cl_ord_id.to_owned(),
+                        symbol: symbol.to_owned(),
+                        side,
+                        order_qty,
+                        cum_qty: 0,
+                        notional: 0,
+                        done: false,
+                    },
+                );
+                let report = self.execution_report(order_id, "0", "0");
+                self.send(report, outgoing);
+                self.report_match(order_id, &match_info, outgoing);
+            }
+            Err(err) => {
+                let reason = match err {
+                    LivreError::DuplicateOrderId => "6",
+                    LivreError::OrderExpired => "4",
+                    _ => "99",
+                };
+                self.reject_order(message, &err.to_string(), reason, outgoing);
+            }
+        }
+    }
+
+    fn on_cancel_request<M: MatchingAlgorithm>(
+        &mut self,
+        message: &FixMessage,
+        book: &mut Orderbook<M>,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        for tag in [tags::CL_ORD_ID, tags::ORIG_CL_ORD_ID] {
+            if message.get(tag).is_none() {
+                self.reject(message, tag, REQUIRED_TAG_MISSING, outgoing);
+                return;
+            }
+        }
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default();
+        let orig_cl_ord_id = message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default();
+        let Some(&order_id) = self.cl_ord_ids.get(orig_cl_ord_id) else {
+            self.cancel_reject(message, None, "1", "unknown order", outgoing);
+            return;
+        };
+
+        match book.cancel_order(order_id) {
+            Ok(_) => {
+                self.cl_ord_ids.insert(cl_ord_id.to_owned(), order_id);
+                if let Some(order) = self.orders.get_mut(&order_id) {
+                    order.cl_ord_id = cl_ord_id.to_owned();
+                    order.done = true;
+                }
+                let report = self
+                    .execution_report(order_id, "4", "4")
+                    .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id);
+                self.send(report, outgoing);
+            }
+            Err(err) => {
+                self.cancel_reject(message, Some(order_id), "1", &err.to_string(), outgoing);
+            }
+        }
+    }
+
+    fn on_cancel_replace_request<M: MatchingAlgorithm>(
+        &mut self,
+        message: &FixMessage,
+        book: &mut Orderbook<M>,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        for tag in [
+            tags::CL_ORD_ID,
+            tags::ORIG_CL_ORD_ID,
+            tags::SIDE,
+            tags::ORDER_QTY,
+        ] {
+            if message.get(tag).is_none() {
+                self.reject(message, tag, REQUIRED_TAG_MISSING, outgoing);
+                return;
+            }
+        }
+        let cl_ord_id = message.get(tags::CL_ORD_ID).unwrap_or_default();
+        let orig_cl_ord_id = message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default();
+        let Some(&order_id) = self.cl_ord_ids.get(orig_cl_ord_id) else {
+            self.cancel_reject(message, None, "2", "unknown order", outgoing);
+            return;
+        };
+        let price = match message.get(tags::PRICE) {
+            Some(price) => price.parse().ok(),
+            // without a Price the order keeps its current one, the book rejects the
+            // request if it no longer has the order
+            None => Some(book.find_order(order_id).map_or(0, Order::price)),
+        };
+        let (Some(side), Some(order_qty), Some(price)) =
+            (parse_side(message), message.get_u64(tags::ORDER_QTY), price)
+        else {
+            self.cancel_reject(message, Some(order_id), "2", "invalid order", outgoing);
+            return;
+        };
+        // OrderQty includes what has already been filled, the book only sees the rest
+        let cum_qty = self.orders.get(&order_id).map_or(0, |order| order.cum_qty);
+        if order_qty <= cum_qty {
+            self.cancel_reject(
+                message,
+                Some(order_id),
+                "2",
+                "OrderQty must exceed CumQty",
+                outgoing,
+            );
+            return;
+        }
+
+        match book.modify_order(ModifyOrder::new(order_id, side, price, order_qty - cum_qty)) {
+            Ok(match_info) => {
+                self.cl_ord_ids.insert(cl_ord_id.to_owned(), order_id);
+                if let Some(order) = self.orders.get_mut(&order_id) {
+                    order.cl_ord_id = cl_ord_id.to_owned();
+                    order.side = side;
+                    order.order_qty = order_qty;
+                }
+                let ord_status = self
+                    .orders
+                    .get(&order_id)
+                    .map_or("0", SessionOrder::ord_status);
+                let report = self
+                    .execution_report(order_id, "5", ord_status)
+                    .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id);
+                self.send(report, outgoing);
+                self.report_match(order_id, &match_info, outgoing);
+            }
+            Err(err) => {
+                self.cancel_reject(message, Some(order_id), "2", &err.to_string(), outgoing);
+            }
+        }
+    }
+
+    fn parse_order(&mut self, message: &FixMessage, order_id: u64) -> Result<Order, u32> {
+        let side = parse_side(message).ok_or(tags::SIDE)?;
+        let quantity = message
+            .get_u64(tags::ORDER_QTY)
+            .filter(|&quantity| quantity > 0)
+            .ok_or(tags::ORDER_QTY)?;
+        let price = || message.get_u64(tags::PRICE).ok_or(tags::PRICE);
+        let stop_price = || message.get_u64(tags::STOP_PX).ok_or(tags::STOP_PX);
+
+        let time_in_force = || -> Result<OrderType, u32> {
+            Ok(match message.get(tags::TIME_IN_FORCE).unwrap_or("0") {
+                "0" => OrderType::GoodForDay,
+                "1" => OrderType::GoodTillCancel,
+                "3" => OrderType::FillAndKill,
+                "4" => OrderType::FillOrKill,
+                "6" => OrderType::GoodTillDate {
+                    expiry: message
+                        .get(tags::EXPIRE_TIME)
+                        .and_then(parse_utc_timestamp)
+                        .ok_or(tags::EXPIRE_TIME)?,
+                },
+                _ => return Err(tags::TIME_IN_FORCE),
+            })
+        };
+        let (order_type, price) = match message.get(tags::ORD_TYPE) {
+            Some("1") => (OrderType::Market, 0),
+            Some("2") => (time_in_force()?, price()?),
+            Some("3") => (
+                OrderType::StopMarket {
+                    stop_price: stop_price()?,
+                },
+                0,
+            ),
+            Some("4") => (
+                OrderType::StopLimit {
+                    stop_price: stop_price()?,
+                },
+                price()?,
+            ),
+            _ => return Err(tags::ORD_TYPE),
+        };
+
+        let mut order = Order::new(order_type, order_id, side, price, quantity);
+        if let Some(max_floor) = message.get(tags::MAX_FLOOR) {
+            let max_floor = max_floor.parse().map_err(|_| tags::MAX_FLOOR)?;
+            order = order.with_display_quantity(max_floor);
+        }
+        // ExecInst 6 is "participate don't initiate"
+        if message
+            .get(tags::EXEC_INST)
+            .is_some_and(|exec_inst| exec_inst.split(' ').any(|inst| inst == "6"))
+        {
+            order = order.with_post_only(PostOnly::Reject);
+        }
+        if let Some(account) = message.get(tags::ACCOUNT) {
+            let owner = match self.accounts.get(account) {
+                Some(&owner) => owner,
+                None => {
+                    let owner = self.next_owner_id;
+                    self.next_owner_id += 1;
+                    self.accounts.insert(account.to_owned(), owner);
+                    owner
+                }
+            };
+            order = order.with_owner(owner);
+        }
+        Ok(order)
+    }
+
+    /// Reports the fills and cancellations that an accepted request caused.
+    fn report_match(
+        &mut self,
+        order_id: u64,
+        match_info: &MatchInfo,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        for trade in match_info
+            .trade_log
+            .iter()
+            .chain(&match_info.triggered_trade_log)
+        {
+            self.report_fill(trade.taker_order_id, trade, outgoing);
+            self.report_fill(trade.maker_order_id, trade, outgoing);
+        }
+
+        for prevented in &match_info.prevented_trades {
+            if matches!(
+                prevented.mode,
+                SelfTradePrevention::CancelOldest | SelfTradePrevention::CancelBoth
+            ) {
+                self.report_cancelled(prevented.maker_order_id, outgoing);
+            }
+        }
+        if match_info.cancelled_quantity > 0 {
+            self.report_cancelled(order_id, outgoing);
+        }
+    }
+
+    fn report_fill(&mut self, order_id: u64, trade: &Trade, outgoing: &mut Vec<FixMessage>) {
+        let Some(order) = self.orders.get_mut(&order_id) else {
+            return;
+        };
+        order.cum_qty += trade.quantity;
+        order.notional += trade.price as u128 * trade.quantity as u128;
+        let ord_status = order.ord_status();
+        let report = self
+            .execution_report(order_id, "F", ord_status)
+            .with(tags::LAST_QTY, trade.quantity)
+            .with(tags::LAST_PX, trade.price);
+        self.send(report, outgoing);
+    }
+
+    fn report_cancelled(&mut self, order_id: u64, outgoing: &mut Vec<FixMessage>) {
+        let Some(order) = self.orders.get_mut(&order_id) else {
+            return;
+        };
+        order.done = true;
+        let ord_status = order.ord_status();
+        let report = self.execution_report(order_id, "4", ord_status);
+        self.send(report, outgoing);
+    }
+
+    fn execution_report(&mut self, order_id: u64, exec_type: &str, ord_status: &str) -> FixMessage {
+        let exec_id = self.next_exec_id;
+        self.next_exec_id += 1;
+        let mut report = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tags::ORDER_ID, order_id)
+            .with(tags::EXEC_ID, exec_id)
+            .with(tags::EXEC_TYPE, exec_type)
+            .with(tags::ORD_STATUS, ord_status);
+        if let Some(order) = self.orders.get(&order_id) {
+            report = report
+                .with(tags::CL_ORD_ID, &order.cl_ord_id)
+                .with(tags::SYMBOL, &order.symbol)
+                .with(
+                    tags::SIDE,
+                    match order.side {
+                        Side::Bid => "1",
+                        Side::Ask => "2",
+                    },
+                )
+                .with(tags::ORDER_QTY, order.order_qty)
+                .with(tags::LEAVES_QTY, order.leaves_qty())
+                .with(tags::CUM_QTY, order.cum_qty)
+                .with(tags::AVG_PX, order.avg_px());
+        }
+        report.with(tags::TRANSACT_TIME, format_utc_timestamp(self.clock.now()))
+    }
+
+    /// Rejects a NewOrderSingle with an execution report.
+    fn reject_order(
+        &mut self,
+        message: &FixMessage,
+        text: &str,
+        reason: &str,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        let mut report = FixMessage::new(msg_type::EXECUTION_REPORT)
+            .with(tags::ORDER_ID, "NONE")
+            .with(tags::EXEC_ID, self.next_exec_id)
+            .with(tags::EXEC_TYPE, "8")
+            .with(tags::ORD_STATUS, "8")
+            .with(tags::ORD_REJ_REASON, reason)
+            .with(tags::LEAVES_QTY, 0)
+            .with(tags::CUM_QTY, 0)
+            .with(tags::AVG_PX, 0)
+            .with(tags::TEXT, text);
+        self.next_exec_id += 1;
+        for tag in [tags::CL_ORD_ID, tags::SYMBOL, tags::SIDE, tags::ORDER_QTY] {
+            if let Some(value) = message.get(tag) {
+                report.set(tag, value);
+            }
+        }
+        self.send(report, outgoing);
+    }
+
+    fn cancel_reject(
+        &mut self,
+        message: &FixMessage,
+        order_id: Option<u64>,
+        response_to: &str,
+        text: &str,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        let ord_status = order_id
+            .and_then(|order_id| self.orders.get(&order_id))
+            .map_or("8", SessionOrder::ord_status);
+        let reject = FixMessage::new(msg_type::ORDER_CANCEL_REJECT)
+            .with(
+                tags::ORDER_ID,
+                order_id.map_or_else(|| "NONE".to_owned(), |order_id| order_id.to_string()),
+            )
+            .with(
+                tags::CL_ORD_ID,
+                message.get(tags::CL_ORD_ID).unwrap_or_default(),
+            )
+            .with(
+                tags::ORIG_CL_ORD_ID,
+                message.get(tags::ORIG_CL_ORD_ID).unwrap_or_default(),
+            )
+            .with(tags::ORD_STATUS, ord_status)
+            .with(tags::CXL_REJ_RESPONSE_TO, response_to)
+            .with(
+                tags::CXL_REJ_REASON,
+                if order_id.is_some() { "0" } else { "1" },
+            )
+            .with(tags::TEXT, text);
+        self.send(reject, outgoing);
+    }
+
+    /// Sends a session level Reject for a message that could not be processed.
+    fn reject(
+        &mut self,
+        message: &FixMessage,
+        ref_tag_id: u32,
+        reason: u32,
+        outgoing: &mut Vec<FixMessage>,
+    ) {
+        let reject = FixMessage::new(msg_type::REJECT)
+            .with(
+                tags::REF_SEQ_NUM,
+                message.get(tags::MSG_SEQ_NUM).unwrap_or_default(),
+            )
+            .with(tags::REF_TAG_ID, ref_tag_id)
+            .with(tags::REF_MSG_TYPE, message.msg_type())
+            .with(tags::SESSION_REJECT_REASON, reason);
+        self.send(reject, outgoing);
+    }
+
+    fn header(&self, msg_type: &str, seq_num: u64, sending_time: &str) -> FixMessage {
+        FixMessage::new(msg_type)
+            .with(tags::SENDER_COMP_ID, &self.sender_comp_id)
+            .with(tags::TARGET_COMP_ID, &self.target_comp_id)
+            .with(tags::MSG_SEQ_NUM, seq_num)
+            .with(tags::SENDING_TIME, sending_time)
+    }
+
+    /// Stamps the message with the session header and the next sequence number and
+    /// keeps it for resend requests.
+    fn send(&mut self, body: FixMessage, outgoing: &mut Vec<FixMessage>) {
+        let now = self.clock.now();
+        let seq_num = self.next_outgoing_seq;
+        self.next_outgoing_seq += 1;
+        self.last_sent = now;
+
+        let mut message = self.header(body.msg_type(), seq_num, &format_utc_timestamp(now));
+        for (tag, value) in body.fields().skip(1) {
+            message.set(tag, value);
+        }
+        self.sent.insert(seq_num, message.clone());
+        outgoing.push(message);
+    }
+}
+
+fn parse_side(message: &FixMessage) -> Option<Side> {
+    match message.get(tags::SIDE)? {
+        "1" => Some(Side::Bid),
+        "2" => Some(Side::Ask),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+
+    struct Counterparty {
+        session: FixSession,
+        book: Orderbook,
+        next_seq: u64,
+    }
+
+    impl Counterparty {
+        fn logged_on() -> Self {
+            let mut session = FixSession::new("LIVRE", "CLIENT");
+            session.set_clock(ManualClock::new(0));
+            let mut counterparty = Self {
+                session,
+                book: Orderbook::new(),
+                next_seq: 1,
+            };
+            let replies = counterparty.send(
+                FixMessage::new(msg_type::LOGON)
+                    .with(tags::ENCRYPT_METHOD, 0)
+                    .with(tags::HEART_BT_INT, 30),
+            );
+            assert_eq!(replies[0].msg_type(), msg_type::LOGON);
+            assert_eq!(counterparty.session.state(), SessionState::Active);
+            counterparty
+        }
+
+        fn send(&mut self, body: FixMessage) -> Vec<FixMessage> {
+            let mut message = FixMessage::new(body.msg_type())
+                .with(tags::SENDER_COMP_ID, "CLIENT")
+                .with(tags::TARGET_COMP_ID, "LIVRE")
+                .with(tags::MSG_SEQ_NUM, self.next_seq)
+                .with(tags::SENDING_TIME, "20240102-09:00:00");
+            for (tag, value) in body.fields().skip(1) {
+                message.set(tag, value);
+            }
+            self.next_seq += 1;
+            self.session.on_message(&message, &mut self.book)
+        }
+
+        fn new_order(&mut self, cl_ord_id: &str, side: &str, price: u64, quantity: u64) {
+            let replies = self.send(
+                FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                    .with(tags::CL_ORD_ID, cl_ord_id)
+                    .with(tags::SIDE, side)
+                    .with(tags::ORDER_QTY, quantity)
+                    .with(tags::ORD_TYPE, 2)
+                    .with(tags::PRICE, price)
+                    .with(tags::TIME_IN_FORCE, 1),
+            );
+            assert_eq!(replies[0].get(tags::EXEC_TYPE), Some("0"));
+        }
+
+        fn replace(&mut self, cl_ord_id: &str, orig_cl_ord_id: &str) -> FixMessage {
+            FixMessage::new(msg_type::ORDER_CANCEL_REPLACE_REQUEST)
+                .with(tags::CL_ORD_ID, cl_ord_id)
+                .with(tags::ORIG_CL_ORD_ID, orig_cl_ord_id)
+                .with(tags::SIDE, 2)
+                .with(tags::ORDER_QTY, 10)
+                .with(tags::ORD_TYPE, 2)
+        }
+    }
+
+    #[test]
+    fn first_message_must_be_a_logon() {
+        let mut session = FixSession::new("LIVRE", "CLIENT");
+        let heartbeat = FixMessage::new(msg_type::HEARTBEAT)
+            .with(tags::SENDER_COMP_ID, "CLIENT")
+            .with(tags::TARGET_COMP_ID, "LIVRE")
+            .with(tags::MSG_SEQ_NUM, 1);
+        let replies = session.on_message(&heartbeat, &mut Orderbook::new());
+        assert_eq!(replies[0].msg_type(), msg_type::LOGOUT);
+        assert_eq!(session.state(), SessionState::LoggedOut);
+    }
+
+    #[test]
+    fn sequence_gap_requests_a_resend() {
+        let mut counterparty = Counterparty::logged_on();
+        counterparty.next_seq += 2;
+        let replies = counterparty.send(FixMessage::new(msg_type::HEARTBEAT));
+        assert_eq!(replies[0].msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(replies[0].get_u64(tags::BEGIN_SEQ_NO), Some(2));
+        assert_eq!(counterparty.session.next_incoming_seq(), 2);
+    }
+
+    #[test]
+    fn crossing_order_is_acknowledged_and_filled() {
+        let mut counterparty = Counterparty::logged_on();
+        counterparty.new_order("B1", "1", 100, 10);
+        let replies = counterparty.send(
+            FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                .with(tags::CL_ORD_ID, "S1")
+                .with(tags::SIDE, 2)
+                .with(tags::ORDER_QTY, 4)
+                .with(tags::ORD_TYPE, 2)
+                .with(tags::PRICE, 100),
+        );
+        let exec_types: Vec<_> = replies
+            .iter()
+            .map(|reply| reply.get(tags::EXEC_TYPE).unwrap())
+            .collect();
+        assert_eq!(exec_types, ["0", "F", "F"]);
+        assert_eq!(replies[1].get(tags::ORD_STATUS), Some("2"));
+        assert_eq!(replies[2].get(tags::CL_ORD_ID), Some("B1"));
+        assert_eq!(replies[2].get(tags::ORD_STATUS), Some("1"));
+        assert_eq!(replies[2].get_u64(tags::LEAVES_QTY), Some(6));
+    }
+
+    #[test]
+    fn replace_without_price_keeps_the_current_price() {
+        let mut counterparty = Counterparty::logged_on();
+        counterparty.new_order("B1", "1", 90, 10);
+        counterparty.new_order("S1", "2", 110, 5);
+
+        let replace = counterparty.replace("S2", "S1");
+        let replies = counterparty.send(replace);
+        assert_eq!(replies[0].get(tags::EXEC_TYPE), Some("5"));
+        let order_id = counterparty.session.order_id("S2").unwrap();
+        let order = counterparty.book.get_order(order_id).unwrap();
+        assert_eq!((order.price, order.remaining_quantity), (110, 10));
+        assert_eq!(counterparty.book.best_bid(), Some(90));
+    }
+
+    #[test]
+    fn replace_with_malformed_price_is_rejected() {
+        let mut counterparty = Counterparty::logged_on();
+        counterparty.new_order("S1", "2", 110, 5);
+
+        let replace = counterparty.replace("S2", "S1").with(tags::PRICE, "1O0");
+        let replies = counterparty.send(replace);
+        assert_eq!(replies[0].msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        assert_eq!(counterparty.session.order_id("S2"), None);
+        let order_id = counterparty.session.order_id("S1").unwrap();
+        assert_eq!(counterparty.book.get_order(order_id).unwrap().price, 110);
+    }
+
+    #[test]
+    fn rejected_replace_leaves_the_order_live() {
+        let mut counterparty = Counterparty::logged_on();
+        counterparty.new_order("B1", "1", 90, 10);
+        let replies = counterparty.send(
+            FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                .with(tags::CL_ORD_ID, "S1")
+                .with(tags::SIDE, 2)
+                .with(tags::ORDER_QTY, 5)
+                .with(tags::ORD_TYPE, 2)
+                .with(tags::PRICE, 110)
+                .with(tags::TIME_IN_FORCE, 1)
+                .with(tags::EXEC_INST, 6),
+        );
+        assert_eq!(replies[0].get(tags::EXEC_TYPE), Some("0"));
+
+        let replace = counterparty.replace("S2", "S1").with(tags::PRICE, 90);
+        let replies = counterparty.send(replace);
+        assert_eq!(replies[0].msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        assert_eq!(replies[0].get(tags::ORD_STATUS), Some("0"));
+        let order_id = counterparty.session.order_id("S1").unwrap();
+        assert!(counterparty.book.get_order(order_id).is_some());
+
+        // the order can still be cancelled under its original ClOrdID
+        let replies = counterparty.send(
+            FixMessage::new(msg_type::ORDER_CANCEL_REQUEST)
+                .with(tags::CL_ORD_ID, "S3")
+                .with(tags::ORIG_CL_ORD_ID, "S1"),
+        );
+        assert_eq!(replies[0].get(tags::EXEC_TYPE), Some("4"));
+    }
+
+    #[test]
+    fn expire_time_beyond_the_timestamp_range_is_rejected() {
+        let mut counterparty = Counterparty::logged_on();
+        let replies = counterparty.send(
+            FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                .with(tags::CL_ORD_ID, "B1")
+                .with(tags::SIDE, 1)
+                .with(tags::ORDER_QTY, 10)
+                .with(tags::ORD_TYPE, 2)
+                .with(tags::PRICE, 100)
+                .with(tags::TIME_IN_FORCE, 6)
+                .with(tags::EXPIRE_TIME, "99991231-23:59:59"),
+        );
+        assert_eq!(replies[0].msg_type(), msg_type::REJECT);
+        assert_eq!(replies[0].get_u64(tags::REF_TAG_ID), Some(126));
+        assert_eq!(counterparty.book.order_count(), 0);
+    }
+
+    #[test]
+    fn accounts_are_mapped_to_owners() {
+        let mut counterparty = Counterparty::logged_on();
+        counterparty.session.set_next_owner_id(100);
+        counterparty
+            .book
+            .set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest));
+        let order = |cl_ord_id, side, account| {
+            FixMessage::new(msg_type::NEW_ORDER_SINGLE)
+                .with(tags::CL_ORD_ID, cl_ord_id)
+                .with(tags::SIDE, side)
+                .with(tags::ORDER_QTY, 10)
+                .with(tags::ORD_TYPE, 2)
+                .with(tags::PRICE, 100)
+                .with(tags::TIME_IN_FORCE, 1)
+                .with(tags::ACCOUNT, account)
+        };
+
+        let replies = counterparty.send(order("B1", 1, "ACME-1"));
+        assert_eq!(replies[0].get(tags::EXEC_TYPE), Some("0"));
+        assert_eq!(counterparty.session.owner_id("ACME-1"), Some(100));
+        // the same account cannot trade with itself
+        let replies = counterparty.send(order("S1", 2, "ACME-1"));
+        assert_eq!(replies[1].get(tags::EXEC_TYPE), Some("4"));
+        assert_eq!(counterparty.book.order_count(), 1);
+
+        let replies = counterparty.send(order("S2", 2, "OTHER"));
+        assert_eq!(counterparty.session.owner_id("OTHER"), Some(101));
+        assert_eq!(replies[1].get(tags::EXEC_TYPE), Some("F"));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{LivreError, Order, OrderType};
+
+/// Reference data of the instrument a book trades: the grid prices must sit on and
+/// the quantities orders may have. The default accepts every price and quantity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrumentSpec {
+    // (lowest price, tick size) pairs sorted by price
+    tick_table: Vec<(u64, u64)>,
+    lot_size: u64,
+    min_quantity: Option<u64>,
+    max_quantity: Option<u64>,
+}
+
+impl InstrumentSpec {
+    /// # Panics
+    ///
+    /// Panics if `tick_size` is zero.
+    pub fn new(tick_size: u64) -> Self {
+        assert!(tick_size > 0, "tick size must be positive");
+        Self {
+            tick_table: vec![(0, tick_size)],
+            lot_size: 1,
+            min_quantity: None,
+            max_quantity: None,
+        }
+    }
+
+    /// Uses a tick size that depends on the price. Each tier is the lowest price it
+    /// applies to and its tick size; prices below the lowest tier use its tick.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no tiers or a tick size is zero.
+    pub fn with_tick_table(mut self, tiers: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        let mut tick_table: Vec<(u64, u64)> = tiers.into_iter().collect();
+        assert!(!tick_table.is_empty(), "tick table needs at least one tier");
+        assert!(
+            tick_table.iter().all(|&(_, tick_size)| tick_size > 0),
+            "tick size must be positive"
+        );
+        tick_table.sort_unstable();
+        self.tick_table = tick_table;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `lot_size` is zero.
+    pub fn with_lot_size(mut self, lot_size: u64) -> Self {
+        assert!(lot_size > 0, "lot size must be positive");
+        self.lot_size = lot_size;
+        self
+    }
+
+    pub fn with_min_quantity(mut self, min_quantity: u64) -> Self {
+        self.min_quantity = Some(min_quantity);
+        self
+    }
+
+    pub fn with_max_quantity(mut self, max_quantity: u64) -> Self {
+        self.max_quantity = Some(max_quantity);
+        self
+    }
+
+    pub fn tick_table(&self) -> &[(u64, u64)] {
+        &self.tick_table
+    }
+
+    pub fn lot_size(&self) -> u64 {
+        self.lot_size
+    }
+
+    pub fn min_quantity(&self) -> Option<u64> {
+        self.min_quantity
+    }
+
+    pub fn max_quantity(&self) -> Option<u64> {
+        self.max_quantity
+    }
+
+    /// The tick size that applies at `price`.
+    pub fn tick_size(&self, price: u64) -> u64 {
+        let tier = self
+            .tick_table
+            .iter()
+            .rev()
+            .find(|&&(from_price, _)| from_price <= price)
+            .unwrap_or(&self.tick_table[0]);
+        tier.1
+    }
+
+    pub fn check_price(&self, price: u64) -> Result<(), LivreError> {
+        let tick_size = self.tick_size(price);
+        if !price.is_multiple_of(tick_size) {
+            return Err(LivreError::PriceOffTick { price, tick_size });
+        }
+        Ok(())
+    }
+
+    pub fn check_quantity(&self, quantity: u64) -> Result<(), LivreError> {
+        if !quantity.is_multiple_of(self.lot_size) {
+            return Err(LivreError::QuantityOffLot {
+                quantity,
+                lot_size: self.lot_size,
+            });
+        }
+        match (self.min_quantity, self.max_quantity) {
+            (Some(minimum), _) if quantity < minimum => {
+                Err(LivreError::QuantityBelowMinimum { quantity, minimum })
+            }
+            (_, Some(maximum)) if quantity > maximum => {
+                Err(LivreError::QuantityAboveMaximum { quantity, maximum })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks every price an order carries, its quantity and the displayed slice of
+    /// an iceberg order.
+    pub(crate) fn check_order(&self, order: &Order) -> Result<(), LivreError> {
+        match order.order_type {
+            OrderType::Market => {}
+            OrderType::StopMarket { stop_price } => self.check_price(stop_price)?,
+            OrderType::StopLimit { stop_price } => {
+                self.check_price(stop_price)?;
+                self.check_price(order.price)?;
+            }
+            _ => self.check_price(order.price)?,
+        }
+        self.check_quantity(order.initial_quantity)?;
+        if let Some(display_quantity) = order.display_quantity {
+            if display_quantity == 0 {
+                return Err(LivreError::ZeroDisplayQuantity);
+            }
+            if !display_quantity.is_multiple_of(self.lot_size) {
+                return Err(LivreError::QuantityOffLot {
+                    quantity: display_quantity,
+                    lot_size: self.lot_size,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The highest price on the grid below `price`.
+    pub(crate) fn price_below(&self, price: u64) -> Option<u64> {
+        let below = price.checked_sub(1)?;
+        let tick_size = self.tick_size(below);
+        Some(below / tick_size * tick_size)
+    }
+
+    /// The lowest price on the grid above `price`.
+    pub(crate) fn price_above(&self, price: u64) -> Option<u64> {
+        let above = price.checked_add(1)?;
+        let tick_size = self.tick_size(above);
+        above.div_ceil(tick_size).checked_mul(tick_size)
+    }
+}
+
+impl Default for InstrumentSpec {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Orderbook, PostOnly, Side};
+
+    fn tiered() -> InstrumentSpec {
+        InstrumentSpec::default().with_tick_table([(100, 10), (0, 1), (10, 5)])
+    }
+
+    #[test]
+    fn tick_size_depends_on_the_price_tier() {
+        let instrument = tiered();
+        assert_eq!(instrument.tick_table(), [(0, 1), (10, 5), (100, 10)]);
+        assert_eq!(instrument.tick_size(9), 1);
+        assert_eq!(instrument.tick_size(10), 5);
+        assert_eq!(instrument.tick_size(99), 5);
+        assert_eq!(instrument.tick_size(100), 10);
+        assert_eq!(instrument.check_price(95), Ok(()));
+        assert_eq!(
+            instrument.check_price(101),
+            Err(LivreError::PriceOffTick {
+                price: 101,
+                tick_size: 10
+            })
+        );
+    }
+
+    #[test]
+    fn neighbouring_prices_sit_on_the_grid() {
+        let instrument = tiered();
+        assert_eq!(instrument.price_below(100), Some(95));
+        assert_eq!(instrument.price_below(10), Some(9));
+        assert_eq!(instrument.price_below(0), None);
+        assert_eq!(instrument.price_above(97), Some(100));
+        assert_eq!(instrument.price_above(99), Some(100));
+        assert_eq!(instrument.price_above(7), Some(8));
+        assert_eq!(instrument.price_above(u64::MAX), None);
+    }
+
+    #[test]
+    fn quantities_must_be_whole_lots_within_bounds() {
+        let instrument = InstrumentSpec::new(1)
+            .with_lot_size(5)
+            .with_min_quantity(10)
+            .with_max_quantity(100);
+        assert_eq!(instrument.check_quantity(50), Ok(()));
+        assert_eq!(
+            instrument.check_quantity(12),
+            Err(LivreError::QuantityOffLot {
+                quantity: 12,
+                lot_size: 5
+            })
+        );
+        assert_eq!(
+            instrument.check_quantity(5),
+            Err(LivreError::QuantityBelowMinimum {
+                quantity: 5,
+                minimum: 10
+            })
+        );
+        assert_eq!(
+            instrument.check_quantity(105),
+            Err(LivreError::QuantityAboveMaximum {
+                quantity: 105,
+                maximum: 100
+            })
+        );
+        let iceberg =
+            Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 1, 50).with_display_quantity(7);
+        assert_eq!(
+            instrument.check_order(&iceberg),
+            Err(LivreError::QuantityOffLot {
+                quantity: 7,
+                lot_size: 5
+            })
+        );
+    }
+
+    #[test]
+    fn books_enforce_the_instrument() {
+        let mut book = Orderbook::new();
+        book.set_instrument(tiered());
+        assert_eq!(
+            book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 101, 1))
+                .err(),
+            Some(LivreError::PriceOffTick {
+                price: 101,
+                tick_size: 10
+            })
+        );
+        let stop = Order::new(
+            OrderType::StopLimit { stop_price: 97 },
+            1,
+            Side::Bid,
+            100,
+            1,
+        );
+        assert_eq!(
+            book.add_order(stop).err(),
+            Some(LivreError::PriceOffTick {
+                price: 97,
+                tick_size: 5
+            })
+        );
+
+        // sliding post-only orders move to the next price on the grid
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 1))
+            .unwrap();
+        book.add_order(
+            Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 1)
+                .with_post_only(PostOnly::Slide),
+        )
+        .unwrap();
+        assert_eq!(book.get_order(2).unwrap().price, 95);
+    }
+}
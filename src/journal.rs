@@ -0,0 +1,931 @@
+use crate::{
+    auction::UncrossResult,
+    clock::{ManualClock, Timestamp},
+    instrument::InstrumentSpec,
+    matching::MatchingAlgorithm,
+    phase::{BandAction, PhaseChange, PriceBand, TradingPhase},
+    ExpiredOrder, LivreError, MatchInfo, ModifyOrder, Order, OrderType, Orderbook, PostOnly,
+    SelfTradePrevention, Side, Trade,
+};
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// An inbound command that changes the state of an `Orderbook`.
+#[derive(Debug, Clone)]
+pub enum Command {
+    Add(Order),
+    Cancel(u64),
+    Modify(ModifyOrder),
+    /// A call to `Orderbook::expire_orders`.
+    Expire,
+    /// A call to `Orderbook::start_auction`.
+    StartAuction,
+    /// A call to `Orderbook::uncross`.
+    Uncross,
+    /// A call to `Orderbook::set_phase`.
+    SetPhase(TradingPhase),
+    /// A call to `Orderbook::update_phase`.
+    UpdatePhase,
+    /// A call to `Orderbook::set_session_end`.
+    SetSessionEnd(Option<Timestamp>),
+    /// A call to `Orderbook::set_market_protection`.
+    SetMarketProtection(Option<u64>),
+    /// A call to `Orderbook::set_self_trade_prevention`.
+    SetSelfTradePrevention(Option<SelfTradePrevention>),
+    /// A call to `Orderbook::set_schedule`.
+    SetSchedule(Vec<(Timestamp, TradingPhase)>),
+    /// A call to `Orderbook::set_price_band`.
+    SetPriceBand(Option<PriceBand>),
+    /// A call to `Orderbook::set_instrument`.
+    SetInstrument(InstrumentSpec),
+}
+
+/// A journaled command with its sequence number and the book's time when it was
+/// received.
+#[derive(Debug)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub timestamp: Timestamp,
+    pub command: Command,
+}
+
+/// Append-only log of commands, one per line. Commands are written before they are
+/// applied, so the book can be rebuilt with `replay` after a crash.
+pub struct Journal {
+    file: File,
+    last_sequence: u64,
+}
+
+impl Journal {
+    /// Opens the journal at `path` for appending, creating it if needed. Sequence
+    /// numbers continue from the last entry already in the file, and a torn final
+    /// write is truncated so new entries start on a line of their own.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (last_sequence, complete_length) = if path.exists() {
+            let (entries, complete_length) = Self::read_complete(path)?;
+            (
+                entries.last().map_or(0, |entry| entry.sequence),
+                Some(complete_length),
+            )
+        } else {
+            (0, None)
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        if let Some(complete_length) = complete_length {
+            file.set_len(complete_length)?;
+        }
+        Ok(Self {
+            file,
+            last_sequence,
+        })
+    }
+
+    /// Reads every complete entry of the journal at `path`. A final line without a
+    /// newline is a write that was torn by a crash and is ignored.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<JournalEntry>> {
+        Self::read_complete(path).map(|(entries, _)| entries)
+    }
+
+    /// Reads the complete entries along with the length of the file they take up.
+    fn read_complete(path: impl AsRef<Path>) -> io::Result<(Vec<JournalEntry>, u64)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        let mut complete_length = 0;
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if !line.ends_with('\n') {
+                break;
+            }
+            entries.push(decode_entry(line.trim_end()).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed journal entry: {}", line.trim_end()),
+                )
+            })?);
+            complete_length += line.len() as u64;
+            line.clear();
+        }
+        Ok((entries, complete_length))
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Writes a command to the journal, returning its sequence number.
+    pub fn append(&mut self, timestamp: Timestamp, command: &Command) -> io::Result<u64> {
+        let sequence = self.last_sequence + 1;
+        let mut line = encode_entry(sequence, timestamp, command);
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.last_sequence = sequence;
+        Ok(sequence)
+    }
+
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+}
+
+/// Applies journaled commands to `book` in order and returns every trade they
+/// produced, including trades of released stop orders.
+///
+/// The book should be configured like the one that wrote the journal was when the
+/// journal started, later configuration changes are journaled. While replaying,
+/// the book's clock is swapped for a `ManualClock` following the journaled
+/// timestamps, so time dependent behaviour such as expiries replays identically,
+/// and it is put back once the journal has been applied.
+pub fn replay<M: MatchingAlgorithm>(
+    entries: Vec<JournalEntry>,
+    book: &mut Orderbook<M>,
+) -> Vec<Trade> {
+    let clock = ManualClock::default();
+    let original_clock = std::mem::replace(&mut book.clock, Box::new(clock.clone()));
+
+    let mut trades = Vec::new();
+    for entry in entries {
+        clock.set(entry.timestamp);
+        // rejected commands were rejected when they were first applied as well
+        let match_info = match entry.command {
+            Command::Add(order) => book.add_order(order).ok(),
+            Command::Cancel(order_id) => {
+                let _ = book.cancel_order(order_id);
+                None
+            }
+            Command::Modify(order) => book.modify_order(order).ok(),
+            Command::Expire => {
+                book.expire_orders();
+                None
+            }
+            Command::StartAuction => {
+                let _ = book.start_auction();
+                None
+            }
+            Command::Uncross => {
+                if let Ok(result) = book.uncross() {
+                    trades.extend(result.trade_log);
+                    trades.extend(result.triggered_trade_log);
+                }
+                None
+            }
+            Command::SetPhase(phase) => {
+                if let Ok(Some(result)) = book.set_phase(phase) {
+                    trades.extend(result.trade_log);
+                    trades.extend(result.triggered_trade_log);
+                }
+                None
+            }
+            Command::UpdatePhase => {
+                for result in book
+                    .update_phase()
+                    .into_iter()
+                    .filter_map(|change| change.uncross)
+                {
+                    trades.extend(result.trade_log);
+                    trades.extend(result.triggered_trade_log);
+                }
+                None
+            }
+            Command::SetSessionEnd(session_end) => {
+                book.set_session_end(session_end);
+                None
+            }
+            Command::SetMarketProtection(protection) => {
+                book.set_market_protection(protection);
+                None
+            }
+            Command::SetSelfTradePrevention(mode) => {
+                book.set_self_trade_prevention(mode);
+                None
+            }
+            Command::SetSchedule(schedule) => {
+                let _ = book.set_schedule(schedule);
+                None
+            }
+            Command::SetPriceBand(band) => {
+                book.set_price_band(band);
+                None
+            }
+            Command::SetInstrument(instrument) => {
+                book.set_instrument(instrument);
+                None
+            }
+        };
+        if let Some(match_info) = match_info {
+            trades.extend(match_info.trade_log);
+            trades.extend(match_info.triggered_trade_log);
+        }
+    }
+    book.clock = original_clock;
+    trades
+}
+
+/// An `Orderbook` whose commands are written to a `Journal` before being applied.
+pub struct JournaledOrderbook<M> {
+    book: Orderbook<M>,
+    journal: Journal,
+}
+
+impl<M: MatchingAlgorithm> JournaledOrderbook<M> {
+    pub fn new(book: Orderbook<M>, journal: Journal) -> Self {
+        Self { book, journal }
+    }
+
+    pub fn add_order(&mut self, order: Order) -> io::Result<Result<MatchInfo, LivreError>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::Add(order.clone()))?;
+        Ok(self.book.add_order(order))
+    }
+
+    pub fn cancel_order(&mut self, order_id: u64) -> io::Result<Result<Order, LivreError>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::Cancel(order_id))?;
+        Ok(self.book.cancel_order(order_id))
+    }
+
+    pub fn modify_order(
+        &mut self,
+        order: ModifyOrder,
+    ) -> io::Result<Result<MatchInfo, LivreError>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::Modify(order.clone()))?;
+        Ok(self.book.modify_order(order))
+    }
+
+    pub fn expire_orders(&mut self) -> io::Result<Vec<ExpiredOrder>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::Expire)?;
+        Ok(self.book.expire_orders())
+    }
+
+    pub fn start_auction(&mut self) -> io::Result<Result<(), LivreError>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::StartAuction)?;
+        Ok(self.book.start_auction())
+    }
+
+    pub fn uncross(&mut self) -> io::Result<Result<UncrossResult, LivreError>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::Uncross)?;
+        Ok(self.book.uncross())
+    }
+
+    pub fn set_phase(
+        &mut self,
+        phase: TradingPhase,
+    ) -> io::Result<Result<Option<UncrossResult>, LivreError>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::SetPhase(phase))?;
+        Ok(self.book.set_phase(phase))
+    }
+
+    pub fn update_phase(&mut self) -> io::Result<Vec<PhaseChange>> {
+        self.journal
+            .append(self.book.clock.now(), &Command::UpdatePhase)?;
+        Ok(self.book.update_phase())
+    }
+
+    pub fn set_session_end(&mut self, session_end: Option<Timestamp>) -> io::Result<()> {
+        self.journal
+            .append(self.book.clock.now(), &Command::SetSessionEnd(session_end))?;
+        self.book.set_session_end(session_end);
+        Ok(())
+    }
+
+    pub fn set_market_protection(&mut self, protection: Option<u64>) -> io::Result<()> {
+        self.journal.append(
+            self.book.clock.now(),
+            &Command::SetMarketProtection(protection),
+        )?;
+        self.book.set_market_protection(protection);
+        Ok(())
+    }
+
+    pub fn set_self_trade_prevention(
+        &mut self,
+        mode: Option<SelfTradePrevention>,
+    ) -> io::Result<()> {
+        self.journal.append(
+            self.book.clock.now(),
+            &Command::SetSelfTradePrevention(mode),
+        )?;
+        self.book.set_self_trade_prevention(mode);
+        Ok(())
+    }
+
+    pub fn set_schedule(
+        &mut self,
+        schedule: Vec<(Timestamp, TradingPhase)>,
+    ) -> io::Result<Result<(), LivreError>> {
+        self.journal.append(
+            self.book.clock.now(),
+            &Command::SetSchedule(schedule.clone()),
+        )?;
+        Ok(self.book.set_schedule(schedule))
+    }
+
+    pub fn set_price_band(&mut self, band: Option<PriceBand>) -> io::Result<()> {
+        self.journal
+            .append(self.book.clock.now(), &Command::SetPriceBand(band))?;
+        self.book.set_price_band(band);
+        Ok(())
+    }
+
+    pub fn set_instrument(&mut self, instrument: InstrumentSpec) -> io::Result<()> {
+        self.journal.append(
+            self.book.clock.now(),
+            &Command::SetInstrument(instrument.clone()),
+        )?;
+        self.book.set_instrument(instrument);
+        Ok(())
+    }
+
+    pub fn book(&self) -> &Orderbook<M> {
+        &self.book
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn into_inner(self) -> (Orderbook<M>, Journal) {
+        (self.book, self.journal)
+    }
+}
+
+// Entries are space separated fields: the sequence number, the timestamp, the
+// command name and its arguments. Optional values are written as `-`.
+
+fn encode_entry(sequence: u64, timestamp: Timestamp, command: &Command) -> String {
+    let mut line = format!("{sequence} {timestamp} ");
+    // writing to a String cannot fail
+    match command {
+        Command::Add(order) => {
+            write!(
+                line,
+                "ADD {} {} {} {} {} {} {} {}",
+                order.order_id,
+                encode_order_type(order.order_type),
+                encode_side(order.side),
+                order.price,
+                order.initial_quantity,
+                encode_optional(order.display_quantity),
+                match order.post_only {
+                    Some(PostOnly::Reject) => "REJECT",
+                    Some(PostOnly::Slide) => "SLIDE",
+                    None => "-",
+                },
+                encode_optional(order.owner),
+            )
+            .unwrap();
+        }
+        Command::Cancel(order_id) => write!(line, "CANCEL {order_id}").unwrap(),
+        Command::Modify(order) => write!(
+            line,
+            "MODIFY {} {} {} {}",
+            order.order_id,
+            encode_side(order.side),
+            order.price,
+            order.quantity
+        )
+        .unwrap(),
+        Command::Expire => line.push_str("EXPIRE"),
+        Command::StartAuction => line.push_str("AUCTION"),
+        Command::Uncross => line.push_str("UNCROSS"),
+        Command::SetPhase(phase) => write!(line, "PHASE {}", encode_phase(*phase)).unwrap(),
+        Command::UpdatePhase => line.push_str("UPDATE"),
+        Command::SetSessionEnd(session_end) => {
+            write!(line, "SESSIONEND {}", encode_optional(*session_end)).unwrap()
+        }
+        Command::SetMarketProtection(protection) => {
+            write!(line, "PROTECTION {}", encode_optional(*protection)).unwrap()
+        }
+        Command::SetSelfTradePrevention(mode) => write!(
+            line,
+            "STP {}",
+            match mode {
+                None => "-",
+                Some(SelfTradePrevention::CancelNewest) => "NEWEST",
+                Some(SelfTradePrevention::CancelOldest) => "OLDEST",
+                Some(SelfTradePrevention::CancelBoth) => "BOTH",
+                Some(SelfTradePrevention::Decrement) => "DECREMENT",
+            }
+        )
+        .unwrap(),
+        Command::SetSchedule(schedule) => {
+            line.push_str("SCHEDULE");
+            for &(time, phase) in schedule {
+                write!(line, " {time}:{}", encode_phase(phase)).unwrap();
+            }
+        }
+        Command::SetPriceBand(band) => match band {
+            None => line.push_str("BAND -"),
+            Some(band) => write!(
+                line,
+                "BAND {} {}",
+                band.width_bps,
+                match band.action {
+                    BandAction::Halt => "HALT",
+                    BandAction::VolatilityAuction => "VOLATILITY",
+                }
+            )
+            .unwrap(),
+        },
+        Command::SetInstrument(instrument) => {
+            write!(
+                line,
+                "INSTRUMENT {} {} {}",
+                instrument.lot_size(),
+                encode_optional(instrument.min_quantity()),
+                encode_optional(instrument.max_quantity())
+            )
+            .unwrap();
+            for &(from_price, tick_size) in instrument.tick_table() {
+                write!(line, " {from_price}:{tick_size}").unwrap();
+            }
+        }
+    }
+    line
+}
+
+fn decode_entry(line: &str) -> Option<JournalEntry> {
+    let mut fields = line.split(' ');
+    let sequence = fields.next()?.parse().ok()?;
+    let timestamp = fields.next()?.parse().ok()?;
+    let command = match fields.next()? {
+        "ADD" => {
+            let order_id = fields.next()?.parse().ok()?;
+            let order_type = decode_order_type(fields.next()?)?;
+            let side = decode_side(fields.next()?)?;
+            let price = fields.next()?.parse().ok()?;
+            let quantity = fields.next()?.parse().ok()?;
+            let mut order = Order::new(order_type, order_id, side, price, quantity);
+            if let Some(display_quantity) = decode_optional(fields.next()?)? {
+                order = order.with_display_quantity(display_quantity);
+            }
+            match fields.next()? {
+                "REJECT" => order = order.with_post_only(PostOnly::Reject),
+                "SLIDE" => order = order.with_post_only(PostOnly::Slide),
+                "-" => {}
+                _ => return None,
+            }
+            if let Some(owner) = decode_optional(fields.next()?)? {
+                order = order.with_owner(owner);
+            }
+            Command::Add(order)
+        }
+        "CANCEL" => Command::Cancel(fields.next()?.parse().ok()?),
+        "MODIFY" => Command::Modify(ModifyOrder::new(
+            fields.next()?.parse().ok()?,
+            decode_side(fields.next()?)?,
+            fields.next()?.parse().ok()?,
+            fields.next()?.parse().ok()?,
+        )),
+        "EXPIRE" => Command::Expire,
+        "AUCTION" => Command::StartAuction,
+        "UNCROSS" => Command::Uncross,
+        "PHASE" => Command::SetPhase(decode_phase(fields.next()?)?),
+        "UPDATE" => Command::UpdatePhase,
+        "SESSIONEND" => Command::SetSessionEnd(decode_optional(fields.next()?)?),
+        "PROTECTION" => Command::SetMarketProtection(decode_optional(fields.next()?)?),
+        "STP" => Command::SetSelfTradePrevention(match fields.next()? {
+            "-" => None,
+            "NEWEST" => Some(SelfTradePrevention::CancelNewest),
+            "OLDEST" => Some(SelfTradePrevention::CancelOldest),
+            "BOTH" => Some(SelfTradePrevention::CancelBoth),
+            "DECREMENT" => Some(SelfTradePrevention::Decrement),
+            _ => return None,
+        }),
+        "SCHEDULE" => Command::SetSchedule(
+            fields
+                .by_ref()
+                .map(|field| {
+                    let (time, phase) = field.split_once(':')?;
+                    Some((time.parse().ok()?, decode_phase(phase)?))
+                })
+                .collect::<Option<_>>()?,
+        ),
+        "BAND" => Command::SetPriceBand(match fields.next()? {
+            "-" => None,
+            width_bps => Some(PriceBand {
+                width_bps: width_bps.parse().ok()?,
+                action: match fields.next()? {
+                    "HALT" => BandAction::Halt,
+                    "VOLATILITY" => BandAction::VolatilityAuction,
+                    _ => return None,
+                },
+            }),
+        }),
+        "INSTRUMENT" => {
+            let lot_size = fields
+                .next()?
+                .parse()
+                .ok()
+                .filter(|&lot_size| lot_size > 0)?;
+            let min_quantity = decode_optional(fields.next()?)?;
+            let max_quantity = decode_optional(fields.next()?)?;
+            let tick_table = fields
+                .by_ref()
+                .map(|field| {
+                    let (from_price, tick_size) = field.split_once(':')?;
+                    Some((from_price.parse().ok()?, tick_size.parse().ok()?))
+                })
+                .collect::<Option<Vec<(u64, u64)>>>()?;
+            if tick_table.is_empty() || tick_table.iter().any(|&(_, tick_size)| tick_size == 0) {
+                return None;
+            }
+            let mut instrument = InstrumentSpec::default()
+                .with_tick_table(tick_table)
+                .with_lot_size(lot_size);
+            if let Some(min_quantity) = min_quantity {
+                instrument = instrument.with_min_quantity(min_quantity);
+            }
+            if let Some(max_quantity) = max_quantity {
+                instrument = instrument.with_max_quantity(max_quantity);
+            }
+            Command::SetInstrument(instrument)
+        }
+        _ => return None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(JournalEntry {
+        sequence,
+        timestamp,
+        command,
+    })
+}
+
+fn encode_order_type(order_type: OrderType) -> String {
+    match order_type {
+        OrderType::FillAndKill => "FAK".to_owned(),
+        OrderType::GoodTillCancel => "GTC".to_owned(),
+        OrderType::FillOrKill => "FOK".to_owned(),
+        OrderType::GoodForDay => "GFD".to_owned(),
+        OrderType::GoodTillDate { expiry } => format!("GTD:{expiry}"),
+        OrderType::Market => "MKT".to_owned(),
+        OrderType::StopMarket { stop_price } => format!("STOP:{stop_price}"),
+        OrderType::StopLimit { stop_price } => format!("STOPLIMIT:{stop_price}"),
+    }
+}
+
+fn decode_order_type(field: &str) -> Option<OrderType> {
+    let (name, argument) = match field.split_once(':') {
+        Some((name, argument)) => (name, Some(argument.parse().ok()?)),
+        None => (field, None),
+    };
+    Some(match (name, argument) {
+        ("FAK", None) => OrderType::FillAndKill,
+        ("GTC", None) => OrderType::GoodTillCancel,
+        ("FOK", None) => OrderType::FillOrKill,
+        ("GFD", None) => OrderType::GoodForDay,
+        ("GTD", Some(expiry)) => OrderType::GoodTillDate { expiry },
+        ("MKT", None) => OrderType::Market,
+        ("STOP", Some(stop_price)) => OrderType::StopMarket { stop_price },
+        ("STOPLIMIT", Some(stop_price)) => OrderType::StopLimit { stop_price },
+        _ => return None,
+    })
+}
+
+fn encode_side(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "BID",
+        Side::Ask => "ASK",
+    }
+}
+
+fn decode_side(field: &str) -> Option<Side> {
+    match field {
+        "BID" => Some(Side::Bid),
+        "ASK" => Some(Side::Ask),
+        _ => None,
+    }
+}
+
+fn encode_optional(value: Option<u64>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
+
+/// Outer `None` for a malformed field, inner `None` for an absent value.
+fn decode_optional(field: &str) -> Option<Option<u64>> {
+    match field {
+        "-" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
+
+fn encode_phase(phase: TradingPhase) -> &'static str {
+    match phase {
+        TradingPhase::PreOpen => "PREOPEN",
+        TradingPhase::OpeningAuction => "OPENING",
+        TradingPhase::Continuous => "CONTINUOUS",
+        TradingPhase::Halted => "HALTED",
+        TradingPhase::ClosingAuction => "CLOSING",
+        TradingPhase::VolatilityAuction => "VOLATILITY",
+        TradingPhase::Closed => "CLOSED",
+    }
+}
+
+fn decode_phase(field: &str) -> Option<TradingPhase> {
+    match field {
+        "PREOPEN" => Some(TradingPhase::PreOpen),
+        "OPENING" => Some(TradingPhase::OpeningAuction),
+        "CONTINUOUS" => Some(TradingPhase::Continuous),
+        "HALTED" => Some(TradingPhase::Halted),
+        "CLOSING" => Some(TradingPhase::ClosingAuction),
+        "VOLATILITY" => Some(TradingPhase::VolatilityAuction),
+        "CLOSED" => Some(TradingPhase::Closed),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Side;
+    use std::{fs, path::PathBuf};
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("livre-journal-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn book_state<M: MatchingAlgorithm>(book: &Orderbook<M>) -> Vec<crate::OrderView> {
+        book.side_orders(Side::Bid)
+            .chain(book.side_orders(Side::Ask))
+            .collect()
+    }
+
+    #[test]
+    fn entries_round_trip_through_their_encoding() {
+        let commands = [
+            Command::Add(
+                Order::new(
+                    OrderType::GoodTillDate { expiry: 50 },
+                    1,
+                    Side::Ask,
+                    101,
+                    10,
+                )
+                .with_display_quantity(2)
+                .with_post_only(PostOnly::Slide)
+                .with_owner(7),
+            ),
+            Command::Add(Order::new(
+                OrderType::StopLimit { stop_price: 99 },
+                2,
+                Side::Bid,
+                100,
+                5,
+            )),
+            Command::Modify(ModifyOrder::new(1, Side::Ask, 102, 8)),
+            Command::Cancel(2),
+            Command::SetPhase(TradingPhase::ClosingAuction),
+            Command::UpdatePhase,
+            Command::SetSessionEnd(Some(5_000)),
+            Command::SetMarketProtection(None),
+            Command::SetSelfTradePrevention(Some(SelfTradePrevention::CancelBoth)),
+            Command::SetSchedule(vec![
+                (2_000, TradingPhase::ClosingAuction),
+                (3_000, TradingPhase::Closed),
+            ]),
+            Command::SetSchedule(Vec::new()),
+            Command::SetPriceBand(Some(PriceBand {
+                width_bps: 250,
+                action: BandAction::VolatilityAuction,
+            })),
+            Command::SetPriceBand(None),
+            Command::SetInstrument(
+                InstrumentSpec::new(1)
+                    .with_tick_table([(0, 1), (100, 5)])
+                    .with_lot_size(10)
+                    .with_max_quantity(1_000),
+            ),
+        ];
+        for (sequence, command) in commands.iter().enumerate() {
+            let line = encode_entry(sequence as u64, 1_000, command);
+            let entry = decode_entry(&line).unwrap();
+            assert_eq!(entry.sequence, sequence as u64);
+            assert_eq!(entry.timestamp, 1_000);
+            assert_eq!(encode_entry(entry.sequence, 1_000, &entry.command), line);
+        }
+        assert!(decode_entry("1 0 ADD 1 GTC BID 100").is_none());
+        assert!(decode_entry("1 0 INSTRUMENT 1 - - 0:0").is_none());
+    }
+
+    #[test]
+    fn replay_rebuilds_the_same_book_and_trades() {
+        let path = journal_path("replay");
+        let clock = ManualClock::new(1_000);
+        let mut journaled = JournaledOrderbook::new(
+            Orderbook::with_clock(clock.clone()),
+            Journal::open(&path).unwrap(),
+        );
+
+        let mut trades = Vec::new();
+        let mut record = |match_info: Result<MatchInfo, LivreError>| {
+            if let Ok(match_info) = match_info {
+                trades.extend(match_info.trade_log);
+                trades.extend(match_info.triggered_trade_log);
+            }
+        };
+        let gtd = OrderType::GoodTillDate { expiry: 2_000 };
+        record(
+            journaled
+                .add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 101, 10))
+                .unwrap(),
+        );
+        record(
+            journaled
+                .add_order(Order::new(gtd, 2, Side::Ask, 102, 5))
+                .unwrap(),
+        );
+        record(
+            journaled
+                .add_order(
+                    Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 103, 20)
+                        .with_display_quantity(5),
+                )
+                .unwrap(),
+        );
+        record(
+            journaled
+                .add_order(Order::new(
+                    OrderType::StopMarket { stop_price: 102 },
+                    4,
+                    Side::Bid,
+                    0,
+                    5,
+                ))
+                .unwrap(),
+        );
+        record(
+            journaled
+                .add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Bid, 99, 10))
+                .unwrap(),
+        );
+        clock.advance(500);
+        record(
+            journaled
+                .add_order(Order::new(OrderType::FillAndKill, 6, Side::Bid, 102, 12))
+                .unwrap(),
+        );
+        record(
+            journaled
+                .modify_order(ModifyOrder::new(5, Side::Bid, 100, 15))
+                .unwrap(),
+        );
+        // rejected commands are journaled too and replay as rejections
+        record(
+            journaled
+                .add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Bid, 98, 1))
+                .unwrap(),
+        );
+        journaled.cancel_order(42).unwrap().unwrap_err();
+        clock.advance(1_000);
+        journaled.expire_orders().unwrap();
+        record(
+            journaled
+                .add_order(Order::new(OrderType::Market, 7, Side::Bid, 0, 8))
+                .unwrap(),
+        );
+        assert!(!trades.is_empty());
+
+        let (book, journal) = journaled.into_inner();
+        assert_eq!(journal.last_sequence(), 11);
+        let mut replayed = Orderbook::new();
+        let replayed_trades = replay(Journal::read(&path).unwrap(), &mut replayed);
+        assert_eq!(replayed_trades, trades);
+        assert_eq!(book_state(&replayed), book_state(&book));
+        assert_eq!(replayed.stop_order_count(), book.stop_order_count());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_truncates_a_torn_final_write() {
+        let path = journal_path("torn");
+        let mut journal = Journal::open(&path).unwrap();
+        journal.append(1, &Command::Cancel(1)).unwrap();
+        drop(journal);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"2 2 CANC").unwrap();
+        drop(file);
+
+        assert_eq!(Journal::read(&path).unwrap().len(), 1);
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.last_sequence(), 1);
+        journal.append(3, &Command::Cancel(3)).unwrap();
+
+        let entries = Journal::read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[1].sequence, entries[1].timestamp), (2, 3));
+        assert!(matches!(entries[1].command, Command::Cancel(3)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replayed_trades_include_auction_uncrosses() {
+        let path = journal_path("auction");
+        let mut journaled =
+            JournaledOrderbook::new(Orderbook::new(), Journal::open(&path).unwrap());
+        journaled.set_phase(TradingPhase::Halted).unwrap().unwrap();
+        journaled
+            .set_phase(TradingPhase::OpeningAuction)
+            .unwrap()
+            .unwrap();
+        journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 101, 10))
+            .unwrap()
+            .unwrap();
+        journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 99, 10))
+            .unwrap()
+            .unwrap();
+        let uncross = journaled
+            .set_phase(TradingPhase::Continuous)
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(uncross.trade_log.len(), 1);
+
+        let mut replayed = Orderbook::new();
+        let trades: Vec<Trade> = replay(Journal::read(&path).unwrap(), &mut replayed);
+        assert_eq!(trades, uncross.trade_log);
+        assert_eq!(replayed.phase(), TradingPhase::Continuous);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn replay_applies_journaled_configuration_and_keeps_the_clock() {
+        let path = journal_path("configuration");
+        let clock = ManualClock::new(1_000);
+        let mut journaled = JournaledOrderbook::new(
+            Orderbook::with_clock(clock.clone()),
+            Journal::open(&path).unwrap(),
+        );
+        journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10).with_owner(7))
+            .unwrap()
+            .unwrap();
+        journaled
+            .set_self_trade_prevention(Some(SelfTradePrevention::CancelNewest))
+            .unwrap();
+        journaled.set_session_end(Some(1_500)).unwrap();
+        journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 5).with_owner(7))
+            .unwrap()
+            .unwrap();
+        journaled
+            .add_order(Order::new(OrderType::GoodForDay, 3, Side::Bid, 99, 5))
+            .unwrap()
+            .unwrap();
+        clock.advance(1_000);
+        assert_eq!(journaled.expire_orders().unwrap().len(), 1);
+        journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 4, Side::Bid, 100, 1))
+            .unwrap()
+            .unwrap();
+        journaled
+            .set_price_band(Some(PriceBand {
+                width_bps: 500,
+                action: BandAction::Halt,
+            }))
+            .unwrap();
+        journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Ask, 90, 1))
+            .unwrap()
+            .unwrap();
+        // the trade at 90 is outside the band, so the book halts instead
+        let match_info = journaled
+            .add_order(Order::new(OrderType::GoodTillCancel, 6, Side::Bid, 100, 5))
+            .unwrap()
+            .unwrap();
+        assert!(match_info.band_breach.is_some());
+        let (book, _) = journaled.into_inner();
+        assert_eq!(book.get_order(1).unwrap().remaining_quantity, 9);
+        assert_eq!(book.phase(), TradingPhase::Halted);
+
+        let replay_clock = ManualClock::new(9_000);
+        let mut replayed = Orderbook::with_clock(replay_clock.clone());
+        assert_eq!(
+            replay(Journal::read(&path).unwrap(), &mut replayed).len(),
+            1
+        );
+        assert_eq!(book_state(&replayed), book_state(&book));
+        assert_eq!(replayed.phase(), TradingPhase::Halted);
+        // the replayed book runs on its own clock again
+        assert_eq!(replayed.clock.now(), 9_000);
+        replay_clock.advance(1);
+        assert_eq!(replayed.clock.now(), 9_001);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,36 +1,183 @@
+pub mod auction;
+pub mod batch;
+pub mod clock;
+pub mod engine;
+pub mod fix;
+pub mod instrument;
+pub mod journal;
+pub mod listener;
+pub mod market_data;
+pub mod matching;
+pub mod phase;
+pub mod protocol;
+pub mod repl;
+pub mod report;
+pub mod risk;
+pub mod server;
+pub mod snapshot;
+
+use auction::{AuctionIndication, UncrossResult};
+use clock::{Clock, SystemClock, Timestamp};
+use instrument::InstrumentSpec;
+use journal::Command;
+use listener::OrderbookListener;
+use market_data::{MarketDataEvent, MarketDataPublisher};
+use matching::{MatchingAlgorithm, PriceTimePriority};
+use phase::{BandAction, BandBreach, PhaseChange, PriceBand, TradingPhase};
+use report::{CancelReason, ExecutionReport, Liquidity};
 use std::{
     cmp::min,
-    collections::{BTreeMap, HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     error::Error,
     fmt::Display,
 };
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum LivreError {
     UnfillableOrder,
     OrderNotFound,
     DuplicateOrderId,
+    PostOnlyWouldCross,
+    OrderExpired,
+    UnknownInstrument,
+    DuplicateInstrument,
+    NotAllowedInAuction,
+    MarketClosed,
+    TradingHalted,
+    InvalidPhaseTransition,
+    PriceOffTick { price: u64, tick_size: u64 },
+    QuantityOffLot { quantity: u64, lot_size: u64 },
+    QuantityBelowMinimum { quantity: u64, minimum: u64 },
+    QuantityAboveMaximum { quantity: u64, maximum: u64 },
+    ZeroDisplayQuantity,
 }
 
 impl Display for LivreError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.write_str(match self {
-            LivreError::UnfillableOrder => "could not fill order",
-            LivreError::DuplicateOrderId => "order id already in use",
-            LivreError::OrderNotFound => "could not find order matching id",
-        })
+        match self {
+            LivreError::UnfillableOrder => f.write_str("could not fill order"),
+            LivreError::DuplicateOrderId => f.write_str("order id already in use"),
+            LivreError::OrderNotFound => f.write_str("could not find order matching id"),
+            LivreError::PostOnlyWouldCross => f.write_str("post-only order would cross the book"),
+            LivreError::OrderExpired => f.write_str("order expiry is not in the future"),
+            LivreError::UnknownInstrument => f.write_str("no book for instrument"),
+            LivreError::DuplicateInstrument => f.write_str("instrument already has a book"),
+            LivreError::NotAllowedInAuction => {
+                f.write_str("order type cannot be entered during an auction")
+            }
+            LivreError::MarketClosed => f.write_str("market is not open for orders"),
+            LivreError::TradingHalted => f.write_str("trading is halted"),
+            LivreError::InvalidPhaseTransition => {
+                f.write_str("session cannot move to the requested phase")
+            }
+            LivreError::PriceOffTick { price, tick_size } => {
+                write!(
+                    f,
+                    "price {price} is not a multiple of the tick size {tick_size}"
+                )
+            }
+            LivreError::QuantityOffLot { quantity, lot_size } => {
+                write!(
+                    f,
+                    "quantity {quantity} is not a multiple of the lot size {lot_size}"
+                )
+            }
+            LivreError::QuantityBelowMinimum { quantity, minimum } => {
+                write!(f, "quantity {quantity} is below the minimum of {minimum}")
+            }
+            LivreError::QuantityAboveMaximum { quantity, maximum } => {
+                write!(f, "quantity {quantity} is above the maximum of {maximum}")
+            }
+            LivreError::ZeroDisplayQuantity => {
+                f.write_str("iceberg display quantity must be positive")
+            }
+        }
     }
 }
 
 impl Error for LivreError {}
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderType {
     FillAndKill,
     GoodTillCancel,
     FillOrKill,
+    /// Rests like `GoodTillCancel` until the book's session ends, see
+    /// `Orderbook::expire_orders` and `Orderbook::end_of_day`.
     GoodForDay,
+    /// Rests like `GoodTillCancel` until the book's clock reaches `expiry`, see
+    /// `Orderbook::expire_orders`.
+    GoodTillDate {
+        expiry: Timestamp,
+    },
+    /// Sweeps the opposite side regardless of the order's price, up to the book's
+    /// market protection band if one is set. Any unfilled remainder is cancelled.
     Market,
+    /// Rests in the trigger book until a trade prints at or through `stop_price`,
+    /// then enters the book as a `Market` order.
+    StopMarket {
+        stop_price: u64,
+    },
+    /// Rests in the trigger book until a trade prints at or through `stop_price`,
+    /// then enters the book as a `GoodTillCancel` order at the order's limit price.
+    StopLimit {
+        stop_price: u64,
+    },
+}
+
+impl OrderType {
+    fn stop_price(&self) -> Option<u64> {
+        match *self {
+            OrderType::StopMarket { stop_price } | OrderType::StopLimit { stop_price } => {
+                Some(stop_price)
+            }
+            _ => None,
+        }
+    }
+
+    fn expiry(&self) -> Option<Timestamp> {
+        match *self {
+            OrderType::GoodTillDate { expiry } => Some(expiry),
+            _ => None,
+        }
+    }
+
+    /// Whether unfilled quantity of the order rests in the book.
+    fn rests(&self) -> bool {
+        matches!(
+            self,
+            OrderType::GoodForDay | OrderType::GoodTillCancel | OrderType::GoodTillDate { .. }
+        )
+    }
+
+    fn triggered(&self) -> OrderType {
+        match self {
+            OrderType::StopMarket { .. } => OrderType::Market,
+            OrderType::StopLimit { .. } => OrderType::GoodTillCancel,
+            other => *other,
+        }
+    }
+}
+
+/// What to do with a post-only order whose price would cross the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostOnly {
+    Reject,
+    /// Reprice the order one tick away from the best opposite price so it rests.
+    Slide,
+}
+
+/// How an `Orderbook` resolves an incoming order that would trade against a resting
+/// order with the same owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTradePrevention {
+    /// Cancel the rest of the incoming order.
+    CancelNewest,
+    /// Cancel the resting order and keep matching the incoming one.
+    CancelOldest,
+    CancelBoth,
+    /// Reduce both orders by the smaller of their remaining quantities.
+    Decrement,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -39,14 +186,14 @@ pub enum Side {
     Ask,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderState {
     Filled,
     PartialFill(u64),
     Unfilled,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Order {
     order_id: u64,
     order_type: OrderType,
@@ -54,6 +201,11 @@ pub struct Order {
     price: u64,
     initial_quantity: u64,
     remaining_quantity: u64,
+    // iceberg orders only show `display_quantity` at a time, the rest is held in reserve
+    display_quantity: Option<u64>,
+    visible_quantity: u64,
+    post_only: Option<PostOnly>,
+    owner: Option<u64>,
 }
 
 impl Order {
@@ -71,9 +223,68 @@ impl Order {
             price,
             initial_quantity: quantity,
             remaining_quantity: quantity,
+            display_quantity: None,
+            visible_quantity: quantity,
+            post_only: None,
+            owner: None,
         }
     }
 
+    /// Tags the order with the account it belongs to, used for self-trade prevention.
+    pub fn with_owner(mut self, owner: u64) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
+    /// Makes the order maker-only: it is never matched on entry and is either
+    /// rejected or repriced if it would cross the book.
+    pub fn with_post_only(mut self, post_only: PostOnly) -> Self {
+        self.post_only = Some(post_only);
+        self
+    }
+
+    /// Turns the order into an iceberg that only shows `display_quantity` while resting,
+    /// replenishing from the hidden reserve each time the visible slice is consumed.
+    pub fn with_display_quantity(mut self, display_quantity: u64) -> Self {
+        self.display_quantity = Some(display_quantity);
+        self.replenish();
+        self
+    }
+
+    pub fn order_id(&self) -> u64 {
+        self.order_id
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn price(&self) -> u64 {
+        self.price
+    }
+
+    pub fn initial_quantity(&self) -> u64 {
+        self.initial_quantity
+    }
+
+    pub fn remaining_quantity(&self) -> u64 {
+        self.remaining_quantity
+    }
+
+    /// The quantity currently shown in the book, which is only part of the
+    /// remaining quantity for iceberg orders.
+    pub fn displayed_quantity(&self) -> u64 {
+        self.visible_quantity
+    }
+
+    pub fn owner(&self) -> Option<u64> {
+        self.owner
+    }
+
     pub fn is_filled(&self) -> bool {
         self.remaining_quantity == 0
     }
@@ -90,14 +301,32 @@ impl Order {
 
     pub fn fill(&mut self, quantity: u64) -> Result<(), u64> {
         if quantity > self.remaining_quantity {
-            Err(self.remaining_quantity - quantity)
+            Err(quantity - self.remaining_quantity)
         } else {
             self.remaining_quantity -= quantity;
+            self.visible_quantity = self.visible_quantity.saturating_sub(quantity);
             Ok(())
         }
     }
+
+    fn decrement(&mut self, quantity: u64) {
+        self.initial_quantity -= quantity;
+        self.remaining_quantity -= quantity;
+        self.visible_quantity = self.visible_quantity.saturating_sub(quantity);
+    }
+
+    fn needs_replenishing(&self) -> bool {
+        self.visible_quantity == 0 && !self.is_filled()
+    }
+
+    fn replenish(&mut self) {
+        self.visible_quantity = match self.display_quantity {
+            Some(display_quantity) => min(display_quantity, self.remaining_quantity),
+            None => self.remaining_quantity,
+        };
+    }
 }
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ModifyOrder {
     order_id: u64,
     side: Side,
@@ -106,16 +335,30 @@ pub struct ModifyOrder {
 }
 
 impl ModifyOrder {
-    fn to_order(&self, order_type: OrderType) -> Order {
-        Order::new(
-            order_type,
+    pub fn new(order_id: u64, side: Side, price: u64, quantity: u64) -> Self {
+        Self {
+            order_id,
+            side,
+            price,
+            quantity,
+        }
+    }
+
+    fn to_order(&self, old_order: &Order) -> Order {
+        let mut order = Order::new(
+            old_order.order_type,
             self.order_id,
             self.side,
             self.price,
             self.quantity,
-        )
+        );
+        order.display_quantity = old_order.display_quantity;
+        order.post_only = old_order.post_only;
+        order.owner = old_order.owner;
+        order
     }
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Trade {
     pub taker_order_id: u64,
     pub maker_order_id: u64,
@@ -135,6 +378,18 @@ impl Trade {
 }
 
 type TradeLog = Vec<Trade>;
+
+/// Records an iceberg order showing a new slice from its reserve. The order loses
+/// its time priority and is moved to the back of its price level.
+#[derive(Debug)]
+pub struct Refill {
+    pub order_id: u64,
+    pub price: u64,
+    pub displayed_quantity: u64,
+    pub remaining_quantity: u64,
+    pub queue_position: usize,
+}
+
 struct LevelIdentifier {
     price: u64,
     side: Side,
@@ -146,9 +401,40 @@ impl LevelIdentifier {
     }
 }
 
+/// A match between two orders of the same owner that was prevented, with the
+/// quantity each side lost to the book's `SelfTradePrevention` mode.
+#[derive(Debug)]
+pub struct PreventedTrade {
+    pub taker_order_id: u64,
+    pub maker_order_id: u64,
+    pub price: u64,
+    pub taker_quantity: u64,
+    pub maker_quantity: u64,
+    pub mode: SelfTradePrevention,
+}
+
 pub struct MatchInfo {
     pub trade_log: TradeLog,
     pub order_state: OrderState,
+    /// Ids of stop orders released from the trigger book by this order's trades,
+    /// in the order they were released.
+    pub triggered_orders: Vec<u64>,
+    /// Trades generated by the released stop orders, including any further
+    /// stops they triggered in turn.
+    pub triggered_trade_log: TradeLog,
+    /// Iceberg refills that happened while matching, including those caused by
+    /// released stop orders.
+    pub refills: Vec<Refill>,
+    /// Remaining quantity of the order that was cancelled instead of resting in the
+    /// book, either because of its type or self-trade prevention.
+    pub cancelled_quantity: u64,
+    pub prevented_trades: Vec<PreventedTrade>,
+    /// Set if the order, or a stop order it triggered, tried to trade outside the
+    /// price band.
+    pub band_breach: Option<BandBreach>,
+    /// Lifecycle reports of the order and of every order it traded with or
+    /// triggered, in the order they happened.
+    pub reports: Vec<ExecutionReport>,
 }
 
 impl MatchInfo {
@@ -156,203 +442,2630 @@ impl MatchInfo {
         Self {
             trade_log,
             order_state,
+            triggered_orders: Vec::new(),
+            triggered_trade_log: Vec::new(),
+            refills: Vec::new(),
+            cancelled_quantity: 0,
+            prevented_trades: Vec::new(),
+            band_breach: None,
+            reports: Vec::new(),
+        }
+    }
+}
+/// Aggregated view of one price level, only counting the displayed part of
+/// iceberg orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: u64,
+    pub quantity: u64,
+    pub order_count: usize,
+}
+
+/// The best price levels of each side of the book, best price first.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepthSnapshot {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
+/// A resting order together with its place in the book. Queue position 0 is the
+/// next order to be matched at its price level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderView {
+    pub order_id: u64,
+    pub order_type: OrderType,
+    pub side: Side,
+    pub price: u64,
+    pub initial_quantity: u64,
+    pub remaining_quantity: u64,
+    pub displayed_quantity: u64,
+    pub order_state: OrderState,
+    pub queue_position: usize,
+}
+
+impl OrderView {
+    fn new(order: &Order, queue_position: usize) -> Self {
+        Self {
+            order_id: order.order_id,
+            order_type: order.order_type,
+            side: order.side,
+            price: order.price,
+            initial_quantity: order.initial_quantity,
+            remaining_quantity: order.remaining_quantity,
+            displayed_quantity: order.visible_quantity,
+            order_state: order.order_state(),
+            queue_position,
         }
     }
 }
-pub struct Orderbook {
+
+/// An order removed from the book because its time in force ran out.
+#[derive(Debug)]
+pub struct ExpiredOrder {
+    pub order: Order,
+    pub expired_at: Timestamp,
+}
+
+/// A limit order book for a single instrument. Liquidity at each price level is
+/// allocated to incoming orders by the matching algorithm `M`.
+pub struct Orderbook<M = PriceTimePriority> {
     bids: BTreeMap<u64, VecDeque<Order>>,
     asks: BTreeMap<u64, VecDeque<Order>>,
     orders: HashMap<u64, LevelIdentifier>,
+    // stop orders keyed by stop price, waiting for a trade to print through them
+    bid_stops: BTreeMap<u64, VecDeque<Order>>,
+    ask_stops: BTreeMap<u64, VecDeque<Order>>,
+    stop_orders: HashMap<u64, LevelIdentifier>,
+    last_trade_price: Option<u64>,
+    market_protection: Option<u64>,
+    self_trade_prevention: Option<SelfTradePrevention>,
+    price_band: Option<PriceBand>,
+    instrument: InstrumentSpec,
+    clock: Box<dyn Clock>,
+    session_end: Option<Timestamp>,
+    // resting good till date orders ordered by expiry, then order id
+    expiries: BTreeSet<(Timestamp, u64)>,
+    // orders accumulate without matching until the auction is uncrossed
+    auction: bool,
+    phase: TradingPhase,
+    // upcoming phase transitions in time order
+    schedule: VecDeque<(Timestamp, TradingPhase)>,
+    matching: M,
+    market_data: MarketDataPublisher,
+    listeners: Vec<Box<dyn OrderbookListener>>,
+}
+
+/// How an order reached `execute_order`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Submission {
+    New,
+    /// The new version of an order removed by `modify_order`. `was_resting` tells
+    /// whether the order it replaces was part of the market data feed, rather than
+    /// waiting in the trigger book.
+    Replacing {
+        was_resting: bool,
+    },
+    /// A stop order released from the trigger book.
+    Triggered,
 }
 
 impl Orderbook {
     pub fn new() -> Self {
+        Self::with_algorithm(PriceTimePriority)
+    }
+
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        let mut orderbook = Self::new();
+        orderbook.set_clock(clock);
+        orderbook
+    }
+}
+
+impl<M: MatchingAlgorithm> Orderbook<M> {
+    pub fn with_algorithm(matching: M) -> Self {
         Self {
             bids: BTreeMap::new(),
             asks: BTreeMap::new(),
             orders: HashMap::new(),
+            bid_stops: BTreeMap::new(),
+            ask_stops: BTreeMap::new(),
+            stop_orders: HashMap::new(),
+            last_trade_price: None,
+            market_protection: None,
+            self_trade_prevention: None,
+            price_band: None,
+            instrument: InstrumentSpec::default(),
+            clock: Box::new(SystemClock),
+            session_end: None,
+            expiries: BTreeSet::new(),
+            auction: false,
+            phase: TradingPhase::Continuous,
+            schedule: VecDeque::new(),
+            matching,
+            market_data: MarketDataPublisher::default(),
+            listeners: Vec::new(),
         }
     }
 
-    pub fn add_order(&mut self, mut order: Order) -> Result<MatchInfo, LivreError> {
-        match order.order_type {
-            OrderType::FillAndKill => {
-                if !self.can_match(order.side, order.price) {
-                    return Err(LivreError::UnfillableOrder);
-                }
-            }
-            OrderType::FillOrKill => {
-                if !self.can_fully_fill(order.price, order.initial_quantity, order.side) {
-                    return Err(LivreError::UnfillableOrder);
-                }
-            }
-            _ => {
-                if self.orders.contains_key(&order.order_id) {
-                    return Err(LivreError::DuplicateOrderId);
-                }
-            }
-        }
-
-        let trade_log = self.match_order(&mut order);
-        let order_state = order.order_state();
-        if !order.is_filled()
-            && matches!(
-                order.order_type,
-                OrderType::GoodForDay | OrderType::Market | OrderType::GoodTillCancel
-            )
-        {
-            self.orders.insert(
-                order.order_id,
-                LevelIdentifier::new(order.price, order.side),
-            );
-            let book_side = match order.side {
-                Side::Ask => &mut self.asks,
-                Side::Bid => &mut self.bids,
-            };
-            book_side
-                .entry(order.price)
-                .or_insert_with(VecDeque::new)
-                .push_back(order);
-        }
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
 
-        Ok(MatchInfo::new(trade_log, order_state))
+    pub fn add_order(&mut self, order: Order) -> Result<MatchInfo, LivreError> {
+        self.check_phase()?;
+        self.instrument.check_order(&order)?;
+        let result = self.submit_order(order, Submission::New);
+        self.publish_indication();
+        result
     }
 
     pub fn cancel_order(&mut self, order_id: u64) -> Result<Order, LivreError> {
-        if let Some(level) = self.orders.get(&order_id) {
-            let book_side = match level.side {
-                Side::Ask => &mut self.asks,
-                Side::Bid => &mut self.bids,
-            };
-
-            let level = book_side.get_mut(&level.price).unwrap();
-            // can can unwrap as we know it exists in our orders map
-            let idx = level
-                .iter()
-                .position(|order| order.order_id == order_id)
-                .unwrap();
-            self.orders
-                .remove(&order_id)
-                .ok_or(LivreError::OrderNotFound)?;
-            level.remove(idx).ok_or(LivreError::OrderNotFound)
-        } else {
-            Err(LivreError::OrderNotFound)
+        let order = self.remove_order(order_id)?;
+        self.notify(|listener| listener.on_order_cancelled(&order));
+        if order.order_type.stop_price().is_none() {
+            self.market_data.publish(MarketDataEvent::OrderDeleted {
+                order_id,
+                side: order.side,
+                price: order.price,
+            });
+            self.publish_level(order.side, order.price);
+            self.publish_indication();
         }
+        Ok(order)
     }
 
+    /// Replaces an order, which loses its time priority. If the replacement is
+    /// rejected the original order stays where it was.
     pub fn modify_order(&mut self, order: ModifyOrder) -> Result<MatchInfo, LivreError> {
-        let old_order = self.cancel_order(order.order_id)?;
-        let order = order.to_order(old_order.order_type);
-        self.add_order(order)
-    }
+        self.check_phase()?;
+        // validate before removing so an invalid replacement leaves the order untouched
+        let (old_order, queue_position) = self
+            .locate_order(order.order_id)
+            .ok_or(LivreError::OrderNotFound)?;
+        let new_order = order.to_order(old_order);
+        self.instrument.check_order(&new_order)?;
+        let old_order = self.remove_order(order.order_id)?;
+        // stop orders waiting in the trigger book are not part of the market data feed
+        let was_resting = old_order.order_type.stop_price().is_none();
+        let result = self.submit_order(new_order, Submission::Replacing { was_resting });
+        if result.is_err() {
+            // replacements are rejected before they touch the book
+            self.reinstate_order(old_order, queue_position);
+            return result;
+        }
 
-    pub fn order_count(&self) -> usize {
-        self.orders.len()
+        if was_resting {
+            match self.orders.get(&order.order_id) {
+                Some(level) if level.side == old_order.side && level.price == old_order.price => {}
+                Some(_) => self.publish_level(old_order.side, old_order.price),
+                None => {
+                    self.market_data.publish(MarketDataEvent::OrderDeleted {
+                        order_id: order.order_id,
+                        side: old_order.side,
+                        price: old_order.price,
+                    });
+                    self.publish_level(old_order.side, old_order.price);
+                }
+            }
+        }
+        self.publish_indication();
+        result
     }
 
-    fn match_order(&mut self, order: &mut Order) -> TradeLog {
-        let mut trade_log = Vec::new();
-
-        match order.side {
-            Side::Bid => {
-                while let Some((best_price, queue)) = self.asks.pop_first() {
-                    if best_price > order.price {
-                        self.asks.insert(best_price, queue);
-                        break;
-                    }
-                    self.match_level(best_price, queue, order, &mut trade_log);
-                }
+    /// Applies a command to the book, returning the lifecycle reports it produced.
+    /// Refused order commands are reported as `Rejected`, while session commands
+    /// the book refuses, such as an invalid phase transition, return the error.
+    pub fn process(&mut self, command: Command) -> Result<Vec<ExecutionReport>, LivreError> {
+        let (order_id, result) = match command {
+            Command::Add(order) => (
+                order.order_id,
+                self.add_order(order).map(|match_info| match_info.reports),
+            ),
+            Command::Modify(order) => (
+                order.order_id,
+                self.modify_order(order)
+                    .map(|match_info| match_info.reports),
+            ),
+            Command::Cancel(order_id) => (
+                order_id,
+                self.cancel_order(order_id).map(|order| {
+                    vec![ExecutionReport::Cancelled {
+                        order_id,
+                        quantity: order.remaining_quantity,
+                        reason: CancelReason::Requested,
+                    }]
+                }),
+            ),
+            Command::StartAuction => {
+                self.start_auction()?;
+                return Ok(Vec::new());
             }
-            Side::Ask => {
-                while let Some((best_price, queue)) = self.bids.pop_last() {
-                    if best_price < order.price {
-                        self.bids.insert(best_price, queue);
-                        break;
-                    }
-                    self.match_level(best_price, queue, order, &mut trade_log);
-                }
+            Command::Uncross => return Ok(self.uncross()?.reports),
+            Command::SetPhase(phase) => {
+                return Ok(self
+                    .set_phase(phase)?
+                    .map_or_else(Vec::new, |uncross| uncross.reports));
+            }
+            Command::UpdatePhase => {
+                return Ok(self
+                    .update_phase()
+                    .into_iter()
+                    .filter_map(|change| change.uncross)
+                    .flat_map(|uncross| uncross.reports)
+                    .collect());
+            }
+            Command::Expire => {
+                return Ok(self
+                    .expire_orders()
+                    .into_iter()
+                    .map(|expired| ExecutionReport::Expired {
+                        order_id: expired.order.order_id,
+                        quantity: expired.order.remaining_quantity,
+                        expired_at: expired.expired_at,
+                    })
+                    .collect());
+            }
+            Command::SetSessionEnd(session_end) => {
+                self.set_session_end(session_end);
+                return Ok(Vec::new());
+            }
+            Command::SetMarketProtection(protection) => {
+                self.set_market_protection(protection);
+                return Ok(Vec::new());
+            }
+            Command::SetSelfTradePrevention(mode) => {
+                self.set_self_trade_prevention(mode);
+                return Ok(Vec::new());
+            }
+            Command::SetSchedule(schedule) => {
+                self.set_schedule(schedule)?;
+                return Ok(Vec::new());
+            }
+            Command::SetPriceBand(band) => {
+                self.set_price_band(band);
+                return Ok(Vec::new());
+            }
+            Command::SetInstrument(instrument) => {
+                self.set_instrument(instrument);
+                return Ok(Vec::new());
             }
         };
-        trade_log
+        Ok(result.unwrap_or_else(|reason| vec![ExecutionReport::Rejected { order_id, reason }]))
     }
 
-    fn match_level(
-        &mut self,
-        best_price: u64,
-        mut queue: VecDeque<Order>,
-        order: &mut Order,
-        trade_log: &mut TradeLog,
-    ) {
-        while !order.is_filled() && !queue.is_empty() {
-            // already checked if queue is empty, so there will always be a front element
-            let maker_order = queue.front_mut().unwrap();
-            let trade_quantity = min(maker_order.remaining_quantity, order.remaining_quantity);
-            // can unwrap as quantity will necessarily be leq than both order's quantity
-            maker_order.fill(trade_quantity).unwrap();
-            order.fill(trade_quantity).unwrap();
-            trade_log.push(Trade::new(
-                order.order_id,
-                maker_order.order_id,
-                best_price,
-                trade_quantity,
-            ));
-            if maker_order.is_filled() {
-                self.orders.remove(&maker_order.order_id);
-                queue.pop_front();
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    /// Moves the session to `phase`, returning the trades of the auction it ended,
+    /// if any. Entering an auction phase starts a call auction, which is uncrossed
+    /// as soon as the session moves on to anything but a halt.
+    pub fn set_phase(&mut self, phase: TradingPhase) -> Result<Option<UncrossResult>, LivreError> {
+        if phase == self.phase {
+            return Ok(None);
+        }
+        if !self.phase.can_transition(phase) {
+            return Err(LivreError::InvalidPhaseTransition);
+        }
+        self.phase = phase;
+        if phase.is_auction() {
+            // a halted auction carries on from where it stopped
+            if !self.auction {
+                self.begin_auction();
             }
+            Ok(None)
+        } else if self.auction && phase != TradingPhase::Halted {
+            Ok(Some(self.finish_auction()))
+        } else {
+            Ok(None)
         }
+    }
 
-        if !queue.is_empty() {
-            self.asks.insert(best_price, queue);
+    /// Replaces the schedule of phase transitions applied by `update_phase`.
+    /// Transitions must be in time order and each must be valid from the one
+    /// before it, starting from the current phase.
+    pub fn set_schedule(
+        &mut self,
+        schedule: Vec<(Timestamp, TradingPhase)>,
+    ) -> Result<(), LivreError> {
+        let mut phase = self.phase;
+        let mut previous_time = 0;
+        for &(time, next) in &schedule {
+            if time < previous_time || !phase.can_transition(next) {
+                return Err(LivreError::InvalidPhaseTransition);
+            }
+            previous_time = time;
+            phase = next;
         }
+        self.schedule = schedule.into();
+        Ok(())
     }
 
-    fn can_match(&self, side: Side, price: u64) -> bool {
-        match side {
-            Side::Ask => {
-                if let Some((&best_price, _)) = self.bids.last_key_value() {
-                    best_price >= price
-                } else {
-                    false
-                }
+    /// Applies every scheduled transition that is due according to the book's
+    /// clock. Transitions made invalid by manual changes since the schedule was set
+    /// are skipped.
+    pub fn update_phase(&mut self) -> Vec<PhaseChange> {
+        let now = self.clock.now();
+        let mut changes = Vec::new();
+        while let Some(&(scheduled_at, phase)) = self.schedule.front() {
+            if scheduled_at > now {
+                break;
             }
-            Side::Bid => {
-                if let Some((&best_price, _)) = self.asks.first_key_value() {
-                    best_price <= price
-                } else {
-                    false
-                }
+            self.schedule.pop_front();
+            if let Ok(uncross) = self.set_phase(phase) {
+                changes.push(PhaseChange {
+                    phase,
+                    scheduled_at,
+                    uncross,
+                });
             }
         }
+        changes
     }
 
-    fn can_fully_fill(&self, price: u64, mut quantity: u64, side: Side) -> bool {
-        if !self.can_match(side, price) {
-            return false;
+    /// Switches a continuous session into a volatility auction, like
+    /// `set_phase(TradingPhase::VolatilityAuction)`, and does nothing if an auction
+    /// phase is already running. Until the auction is uncrossed, orders accumulate
+    /// without matching, even if they cross, and only orders that can rest in the
+    /// book are accepted. Post-only instructions are not checked since every
+    /// auction trade happens at the same price.
+    pub fn start_auction(&mut self) -> Result<(), LivreError> {
+        if self.phase.is_auction() {
+            return Ok(());
         }
+        self.set_phase(TradingPhase::VolatilityAuction).map(|_| ())
+    }
 
-        let level_iter: Box<dyn Iterator<Item = _>> = match side {
-            Side::Ask => Box::new(self.bids.iter()),
-            Side::Bid => Box::new(self.asks.iter().rev()),
+    pub fn in_auction(&self) -> bool {
+        self.auction
+    }
+
+    /// The price and volume the auction would uncross at if it ended now, counting
+    /// the hidden quantity of iceberg orders. `None` if the book is not crossed.
+    pub fn indicative_uncross(&self) -> Option<AuctionIndication> {
+        let level_quantities = |book_side: &BTreeMap<u64, VecDeque<Order>>| -> Vec<(u64, u64)> {
+            book_side
+                .iter()
+                .map(|(&price, queue)| {
+                    (
+                        price,
+                        queue.iter().map(|order| order.remaining_quantity).sum(),
+                    )
+                })
+                .collect()
         };
-        for (&level_price, queue) in level_iter {
-            if (side == Side::Ask && level_price < price)
-                || (side == Side::Bid && level_price > price)
-            {
-                return false;
-            }
-            let level_quantity = queue.iter().map(|order| order.remaining_quantity).sum();
-            if quantity <= level_quantity {
-                return true;
+        auction::equilibrium(
+            &level_quantities(&self.bids),
+            &level_quantities(&self.asks),
+            self.last_trade_price,
+        )
+    }
+
+    /// Ends the running auction phase, executing every trade at the single price
+    /// from `indicative_uncross`. Opening and volatility auctions move on to
+    /// continuous trading and the closing auction closes the session. Fails with
+    /// `InvalidPhaseTransition` outside of an auction phase, including a halted
+    /// auction, which resumes through `set_phase`.
+    ///
+    /// Orders are filled in price then time priority on both sides. Auction trades
+    /// have no aggressor, so they name the buy order as the taker. Self-trade
+    /// prevention treats the buy order as the incoming one as well: `CancelNewest`
+    /// cancels the bid and `CancelOldest` the ask. Prevented quantity does not
+    /// trade, so less than the indicated volume may execute.
+    pub fn uncross(&mut self) -> Result<UncrossResult, LivreError> {
+        self.phase = match self.phase {
+            TradingPhase::OpeningAuction | TradingPhase::VolatilityAuction => {
+                TradingPhase::Continuous
             }
-            quantity -= level_quantity;
+            TradingPhase::ClosingAuction => TradingPhase::Closed,
+            _ => return Err(LivreError::InvalidPhaseTransition),
+        };
+        Ok(self.finish_auction())
+    }
+
+    fn begin_auction(&mut self) {
+        self.auction = true;
+        self.publish_indication();
+    }
+
+    fn finish_auction(&mut self) -> UncrossResult {
+        let indication = self.indicative_uncross();
+        self.auction = false;
+
+        let mut match_info = MatchInfo::new(Vec::new(), OrderState::Unfilled);
+        if let Some(indication) = indication {
+            self.execute_uncross(indication.price, indication.volume, &mut match_info);
+            self.release_stops(&mut match_info);
+        }
+        UncrossResult {
+            indication,
+            trade_log: match_info.trade_log,
+            triggered_orders: match_info.triggered_orders,
+            triggered_trade_log: match_info.triggered_trade_log,
+            reports: match_info.reports,
         }
-        false
     }
-}
 
-impl Default for Orderbook {
-    fn default() -> Self {
-        Self::new()
+    /// Registers a listener to be notified of every later change to the book.
+    pub fn add_listener(&mut self, listener: impl OrderbookListener + 'static) {
+        self.listeners.push(Box::new(listener));
+    }
+
+    pub fn enable_market_data(&mut self) {
+        self.market_data.enable();
+    }
+
+    pub fn market_data(&self) -> &MarketDataPublisher {
+        &self.market_data
+    }
+
+    /// Takes every market data message published since the last drain.
+    pub fn drain_market_data(&mut self) -> Vec<market_data::MarketDataMessage> {
+        self.market_data.drain()
+    }
+
+    /// `submission` is either `New` or `Replacing`, which changes how the order is
+    /// reported and published.
+    fn submit_order(
+        &mut self,
+        mut order: Order,
+        submission: Submission,
+    ) -> Result<MatchInfo, LivreError> {
+        if self.orders.contains_key(&order.order_id)
+            || self.stop_orders.contains_key(&order.order_id)
+        {
+            return Err(LivreError::DuplicateOrderId);
+        }
+
+        let (order_id, side, price, quantity) = (
+            order.order_id,
+            order.side,
+            order.price,
+            order.remaining_quantity,
+        );
+        let replacing = matches!(submission, Submission::Replacing { .. });
+        let accepted = if replacing {
+            ExecutionReport::Replaced {
+                order_id,
+                side,
+                price,
+                quantity,
+            }
+        } else {
+            ExecutionReport::Accepted {
+                order_id,
+                side,
+                price,
+                quantity,
+            }
+        };
+
+        if let Some(stop_price) = order.order_type.stop_price() {
+            if !self.is_triggered(order.side, stop_price) {
+                let mut match_info = MatchInfo::new(Vec::new(), order.order_state());
+                match_info.reports.push(accepted);
+                if replacing {
+                    self.notify(|listener| listener.on_order_modified(&order));
+                } else {
+                    self.notify(|listener| listener.on_order_accepted(&order));
+                }
+                self.insert_stop(stop_price, order);
+                return Ok(match_info);
+            }
+            order.order_type = order.order_type.triggered();
+        }
+
+        let mut match_info = self.execute_order(order, submission)?;
+        match_info.reports.insert(0, accepted);
+        self.release_stops(&mut match_info);
+        Ok(match_info)
+    }
+
+    /// Removes a resting or stop order from the book without publishing anything.
+    fn remove_order(&mut self, order_id: u64) -> Result<Order, LivreError> {
+        if let Some(level) = self.orders.remove(&order_id) {
+            let book_side = match level.side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            let order = Self::remove_from_level(book_side, level.price, order_id)?;
+            if let Some(expiry) = order.order_type.expiry() {
+                self.expiries.remove(&(expiry, order_id));
+            }
+            Ok(order)
+        } else if let Some(level) = self.stop_orders.remove(&order_id) {
+            let stop_side = match level.side {
+                Side::Ask => &mut self.ask_stops,
+                Side::Bid => &mut self.bid_stops,
+            };
+            Self::remove_from_level(stop_side, level.price, order_id)
+        } else {
+            Err(LivreError::OrderNotFound)
+        }
+    }
+
+    /// Finds an order resting in the book or waiting in the trigger book.
+    fn find_order(&self, order_id: u64) -> Option<&Order> {
+        self.locate_order(order_id).map(|(order, _)| order)
+    }
+
+    /// Finds an order resting in the book or waiting in the trigger book together
+    /// with its position in the queue of its level.
+    fn locate_order(&self, order_id: u64) -> Option<(&Order, usize)> {
+        let (book_side, level) = if let Some(level) = self.orders.get(&order_id) {
+            let book_side = match level.side {
+                Side::Ask => &self.asks,
+                Side::Bid => &self.bids,
+            };
+            (book_side, level)
+        } else {
+            let level = self.stop_orders.get(&order_id)?;
+            let stop_side = match level.side {
+                Side::Ask => &self.ask_stops,
+                Side::Bid => &self.bid_stops,
+            };
+            (stop_side, level)
+        };
+        book_side
+            .get(&level.price)?
+            .iter()
+            .enumerate()
+            .find(|(_, order)| order.order_id == order_id)
+            .map(|(queue_position, order)| (order, queue_position))
+    }
+
+    /// Puts an order taken out by `remove_order` back at its place in the book or
+    /// the trigger book.
+    fn reinstate_order(&mut self, order: Order, queue_position: usize) {
+        let (order_id, side) = (order.order_id, order.side);
+        let (book_side, price) = if let Some(stop_price) = order.order_type.stop_price() {
+            self.stop_orders
+                .insert(order_id, LevelIdentifier::new(stop_price, side));
+            let stop_side = match side {
+                Side::Ask => &mut self.ask_stops,
+                Side::Bid => &mut self.bid_stops,
+            };
+            (stop_side, stop_price)
+        } else {
+            if let Some(expiry) = order.order_type.expiry() {
+                self.expiries.insert((expiry, order_id));
+            }
+            self.orders
+                .insert(order_id, LevelIdentifier::new(order.price, side));
+            let book_side = match side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            (book_side, order.price)
+        };
+        book_side
+            .entry(price)
+            .or_insert_with(VecDeque::new)
+            .insert(queue_position, order);
+    }
+
+    /// Limits how far a market order may sweep from the best opposite price at the
+    /// time it arrives. `None` lets market orders sweep the whole book.
+    pub fn set_market_protection(&mut self, protection: Option<u64>) {
+        self.market_protection = protection;
+    }
+
+    /// Sets how matches between orders of the same owner are prevented. `None`
+    /// lets them trade.
+    pub fn set_self_trade_prevention(&mut self, mode: Option<SelfTradePrevention>) {
+        self.self_trade_prevention = mode;
+    }
+
+    /// Sets the band continuous trading must stay within. An order that would
+    /// trade outside it stops matching before the first trade out of the band,
+    /// and the book halts or starts a volatility auction. `None` disables the
+    /// band.
+    pub fn set_price_band(&mut self, band: Option<PriceBand>) {
+        self.price_band = band;
+    }
+
+    /// Sets the instrument reference data new orders and modifications are
+    /// validated against. Orders already in the book are not revalidated.
+    pub fn set_instrument(&mut self, instrument: InstrumentSpec) {
+        self.instrument = instrument;
+    }
+
+    pub fn instrument(&self) -> &InstrumentSpec {
+        &self.instrument
+    }
+
+    /// Sets the time at which `expire_orders` runs the end of day routine.
+    pub fn set_session_end(&mut self, session_end: Option<Timestamp>) {
+        self.session_end = session_end;
+    }
+
+    /// Expires every order whose time in force has run out according to the
+    /// book's clock, `GoodTillDate` orders first in expiry order. Once the session
+    /// end passes, the end of day routine runs and the session end is cleared until
+    /// a new one is set.
+    pub fn expire_orders(&mut self) -> Vec<ExpiredOrder> {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+        while let Some(&(expiry, order_id)) = self.expiries.first() {
+            if expiry > now {
+                break;
+            }
+            // cancelling also drops the order from the expiry index
+            match self.cancel_order(order_id) {
+                Ok(order) => expired.push(ExpiredOrder {
+                    order,
+                    expired_at: expiry,
+                }),
+                Err(_) => {
+                    self.expiries.pop_first();
+                }
+            }
+        }
+
+        match self.session_end {
+            Some(session_end) if now >= session_end => {
+                self.session_end = None;
+                expired.extend(self.end_of_day());
+            }
+            _ => {}
+        }
+        expired
+    }
+
+    /// Removes every resting `GoodForDay` order from the book.
+    pub fn end_of_day(&mut self) -> Vec<ExpiredOrder> {
+        let expired_at = self.clock.now();
+        let order_ids: Vec<u64> = self
+            .bids
+            .values()
+            .chain(self.asks.values())
+            .flatten()
+            .filter(|order| matches!(order.order_type, OrderType::GoodForDay))
+            .map(|order| order.order_id)
+            .collect();
+        order_ids
+            .into_iter()
+            .filter_map(|order_id| self.cancel_order(order_id).ok())
+            .map(|order| ExpiredOrder { order, expired_at })
+            .collect()
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Returns up to `levels` price levels per side.
+    pub fn depth(&self, levels: usize) -> DepthSnapshot {
+        DepthSnapshot {
+            bids: self.side_depth(Side::Bid, levels),
+            asks: self.side_depth(Side::Ask, levels),
+        }
+    }
+
+    /// Returns up to `levels` price levels of one side, best price first.
+    pub fn side_depth(&self, side: Side, levels: usize) -> Vec<PriceLevel> {
+        self.levels(side)
+            .take(levels)
+            .map(|(&price, queue)| PriceLevel {
+                price,
+                quantity: queue.iter().map(Order::displayed_quantity).sum(),
+                order_count: queue.len(),
+            })
+            .collect()
+    }
+
+    /// Looks up a resting order by id. Stop orders waiting in the trigger book are
+    /// not part of the visible book and are not returned.
+    pub fn get_order(&self, order_id: u64) -> Option<OrderView> {
+        let level = self.orders.get(&order_id)?;
+        let book_side = match level.side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        };
+        book_side
+            .get(&level.price)?
+            .iter()
+            .enumerate()
+            .find(|(_, order)| order.order_id == order_id)
+            .map(|(queue_position, order)| OrderView::new(order, queue_position))
+    }
+
+    /// Iterates over the orders resting at one price level in priority order.
+    pub fn level_orders(&self, side: Side, price: u64) -> impl Iterator<Item = OrderView> + '_ {
+        let book_side = match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        };
+        book_side.get(&price).into_iter().flat_map(|queue| {
+            queue
+                .iter()
+                .enumerate()
+                .map(|(queue_position, order)| OrderView::new(order, queue_position))
+        })
+    }
+
+    /// Iterates over every order resting on one side of the book, best price first
+    /// and in priority order within each level.
+    pub fn side_orders(&self, side: Side) -> impl Iterator<Item = OrderView> + '_ {
+        self.levels(side).flat_map(|(_, queue)| {
+            queue
+                .iter()
+                .enumerate()
+                .map(|(queue_position, order)| OrderView::new(order, queue_position))
+        })
+    }
+
+    pub fn best_bid(&self) -> Option<u64> {
+        self.bids.last_key_value().map(|(&price, _)| price)
+    }
+
+    pub fn best_ask(&self) -> Option<u64> {
+        self.asks.first_key_value().map(|(&price, _)| price)
+    }
+
+    /// Difference between the best ask and the best bid, if both sides have orders.
+    pub fn spread(&self) -> Option<u64> {
+        Some(self.best_ask()?.saturating_sub(self.best_bid()?))
+    }
+
+    pub fn stop_order_count(&self) -> usize {
+        self.stop_orders.len()
+    }
+
+    fn execute_order(
+        &mut self,
+        mut order: Order,
+        submission: Submission,
+    ) -> Result<MatchInfo, LivreError> {
+        if let Some(expiry) = order.order_type.expiry() {
+            if expiry <= self.clock.now() {
+                return Err(LivreError::OrderExpired);
+            }
+        }
+
+        if self.auction && !order.order_type.rests() {
+            return Err(LivreError::NotAllowedInAuction);
+        }
+
+        if let OrderType::Market = order.order_type {
+            // market orders match like a fill and kill order at the worst price they may reach
+            order.price = self
+                .market_limit_price(order.side)
+                .ok_or(LivreError::UnfillableOrder)?;
+        }
+
+        if let (Some(post_only), false) = (order.post_only, self.auction) {
+            if self.can_match(order.side, order.price) {
+                order.price = match post_only {
+                    PostOnly::Reject => None,
+                    PostOnly::Slide => self.slide_price(order.side),
+                }
+                .ok_or(LivreError::PostOnlyWouldCross)?;
+            }
+        }
+
+        let unfillable = match order.order_type {
+            OrderType::FillAndKill | OrderType::Market => !self.can_match(order.side, order.price),
+            OrderType::FillOrKill => {
+                !self.can_fully_fill(order.price, order.initial_quantity, order.side)
+            }
+            _ => false,
+        };
+        if unfillable {
+            return Err(LivreError::UnfillableOrder);
+        }
+        match submission {
+            Submission::New => self.notify(|listener| listener.on_order_accepted(&order)),
+            Submission::Replacing { .. } => {
+                self.notify(|listener| listener.on_order_modified(&order))
+            }
+            Submission::Triggered => {}
+        }
+
+        let mut match_info = MatchInfo::new(Vec::new(), order.order_state());
+        let cancelled = !self.auction && self.match_order(&mut order, &mut match_info);
+        match_info.order_state = order.order_state();
+        if order.is_filled() {
+            // self-trade prevention can decrement an order down to nothing
+            if cancelled {
+                self.notify(|listener| listener.on_order_cancelled(&order));
+            }
+            return Ok(match_info);
+        }
+
+        // a halt leaves the book as it was before the breach
+        let halted = self.phase == TradingPhase::Halted;
+        if !cancelled && !halted && order.order_type.rests() {
+            if let Some(expiry) = order.order_type.expiry() {
+                self.expiries.insert((expiry, order.order_id));
+            }
+            self.orders.insert(
+                order.order_id,
+                LevelIdentifier::new(order.price, order.side),
+            );
+            order.replenish();
+            let (order_id, side, price, quantity) = (
+                order.order_id,
+                order.side,
+                order.price,
+                order.visible_quantity,
+            );
+            let book_side = match order.side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            match_info.reports.push(ExecutionReport::Rested {
+                order_id,
+                side,
+                price,
+                remaining_quantity: order.remaining_quantity,
+            });
+            book_side
+                .entry(order.price)
+                .or_insert_with(VecDeque::new)
+                .push_back(order);
+            self.market_data.publish(
+                if submission == (Submission::Replacing { was_resting: true }) {
+                    MarketDataEvent::OrderModified {
+                        order_id,
+                        side,
+                        price,
+                        quantity,
+                    }
+                } else {
+                    MarketDataEvent::OrderAdded {
+                        order_id,
+                        side,
+                        price,
+                        quantity,
+                    }
+                },
+            );
+            self.publish_level(side, price);
+        } else {
+            self.notify(|listener| listener.on_order_cancelled(&order));
+            match_info.cancelled_quantity = order.remaining_quantity;
+            match_info.reports.push(ExecutionReport::Cancelled {
+                order_id: order.order_id,
+                quantity: order.remaining_quantity,
+                reason: if match_info.band_breach.is_some() {
+                    CancelReason::CircuitBreaker
+                } else if cancelled {
+                    CancelReason::SelfTradePrevention
+                } else {
+                    CancelReason::Unfilled
+                },
+            });
+        }
+
+        Ok(match_info)
+    }
+
+    fn insert_stop(&mut self, stop_price: u64, order: Order) {
+        self.stop_orders
+            .insert(order.order_id, LevelIdentifier::new(stop_price, order.side));
+        let stop_side = match order.side {
+            Side::Ask => &mut self.ask_stops,
+            Side::Bid => &mut self.bid_stops,
+        };
+        stop_side
+            .entry(stop_price)
+            .or_insert_with(VecDeque::new)
+            .push_back(order);
+    }
+
+    /// Buy stops trigger on prints at or above their stop price, sell stops on
+    /// prints at or below it.
+    fn is_triggered(&self, side: Side, stop_price: u64) -> bool {
+        match (side, self.last_trade_price) {
+            (Side::Bid, Some(last_price)) => last_price >= stop_price,
+            (Side::Ask, Some(last_price)) => last_price <= stop_price,
+            (_, None) => false,
+        }
+    }
+
+    fn release_stops(&mut self, match_info: &mut MatchInfo) {
+        let mut high = match_info.trade_log.iter().map(|trade| trade.price).max();
+        let mut low = match_info.trade_log.iter().map(|trade| trade.price).min();
+
+        // stops stay in the trigger book while a band breach has stopped continuous trading
+        while self.trading_continuously() {
+            let Some(mut order) = self.next_triggered_stop(low, high) else {
+                break;
+            };
+            match_info.triggered_orders.push(order.order_id);
+            match_info.reports.push(ExecutionReport::Triggered {
+                order_id: order.order_id,
+            });
+            order.order_type = order.order_type.triggered();
+            let (order_id, quantity) = (order.order_id, order.remaining_quantity);
+            let cascade = match self.execute_order(order.clone(), Submission::Triggered) {
+                Ok(cascade) => cascade,
+                Err(err) => {
+                    self.notify(|listener| listener.on_order_cancelled(&order));
+                    // a market order with nothing left to trade against is cancelled like
+                    // any other unfilled market order
+                    match_info.reports.push(match err {
+                        LivreError::UnfillableOrder => ExecutionReport::Cancelled {
+                            order_id,
+                            quantity,
+                            reason: CancelReason::Unfilled,
+                        },
+                        reason => ExecutionReport::Rejected { order_id, reason },
+                    });
+                    continue;
+                }
+            };
+            match_info.refills.extend(cascade.refills);
+            match_info.reports.extend(cascade.reports);
+            match_info.band_breach = match_info.band_breach.or(cascade.band_breach);
+            for trade in cascade.trade_log {
+                high = high.max(Some(trade.price));
+                low = Some(low.map_or(trade.price, |low| low.min(trade.price)));
+                match_info.triggered_trade_log.push(trade);
+            }
+        }
+    }
+
+    fn next_triggered_stop(&mut self, low: Option<u64>, high: Option<u64>) -> Option<Order> {
+        let (stop_side, stop_price) = match (self.bid_stops.first_key_value(), high) {
+            (Some((&stop_price, _)), Some(high)) if stop_price <= high => {
+                (&mut self.bid_stops, stop_price)
+            }
+            _ => match (self.ask_stops.last_key_value(), low) {
+                (Some((&stop_price, _)), Some(low)) if stop_price >= low => {
+                    (&mut self.ask_stops, stop_price)
+                }
+                _ => return None,
+            },
+        };
+        // levels are removed as soon as they are emptied, so the front order exists
+        let level = stop_side.get_mut(&stop_price).unwrap();
+        let order = level.pop_front().unwrap();
+        if level.is_empty() {
+            stop_side.remove(&stop_price);
+        }
+        self.stop_orders.remove(&order.order_id);
+        Some(order)
+    }
+
+    fn remove_from_level(
+        book_side: &mut BTreeMap<u64, VecDeque<Order>>,
+        price: u64,
+        order_id: u64,
+    ) -> Result<Order, LivreError> {
+        let level = book_side.get_mut(&price).ok_or(LivreError::OrderNotFound)?;
+        let idx = level
+            .iter()
+            .position(|order| order.order_id == order_id)
+            .ok_or(LivreError::OrderNotFound)?;
+        let order = level.remove(idx).ok_or(LivreError::OrderNotFound)?;
+        if level.is_empty() {
+            book_side.remove(&price);
+        }
+        Ok(order)
+    }
+
+    /// Returns whether self-trade prevention cancelled the rest of the order.
+    fn match_order(&mut self, order: &mut Order, match_info: &mut MatchInfo) -> bool {
+        // the band stays centred on the price before the order started sweeping
+        let reference_price = self.last_trade_price;
+        match order.side {
+            Side::Bid => {
+                while let Some((best_price, queue)) = self.asks.pop_first() {
+                    if order.is_filled() || best_price > order.price {
+                        self.asks.insert(best_price, queue);
+                        break;
+                    }
+                    if self.breaches_band(reference_price, best_price) {
+                        self.asks.insert(best_price, queue);
+                        self.trip_circuit_breaker(order, reference_price, best_price, match_info);
+                        break;
+                    }
+                    if self.match_level(best_price, queue, order, match_info) {
+                        return true;
+                    }
+                    // a level left in the book could not allocate the order anything more
+                    if !order.is_filled() && self.asks.contains_key(&best_price) {
+                        break;
+                    }
+                }
+            }
+            Side::Ask => {
+                while let Some((best_price, queue)) = self.bids.pop_last() {
+                    if order.is_filled() || best_price < order.price {
+                        self.bids.insert(best_price, queue);
+                        break;
+                    }
+                    if self.breaches_band(reference_price, best_price) {
+                        self.bids.insert(best_price, queue);
+                        self.trip_circuit_breaker(order, reference_price, best_price, match_info);
+                        break;
+                    }
+                    if self.match_level(best_price, queue, order, match_info) {
+                        return true;
+                    }
+                    // a level left in the book could not allocate the order anything more
+                    if !order.is_filled() && self.bids.contains_key(&best_price) {
+                        break;
+                    }
+                }
+            }
+        };
+        false
+    }
+
+    fn trading_continuously(&self) -> bool {
+        !self.auction && self.phase != TradingPhase::Halted
+    }
+
+    fn breaches_band(&self, reference_price: Option<u64>, price: u64) -> bool {
+        match (self.price_band, reference_price) {
+            (Some(band), Some(reference_price)) => !band.contains(reference_price, price),
+            _ => false,
+        }
+    }
+
+    fn trip_circuit_breaker(
+        &mut self,
+        order: &Order,
+        reference_price: Option<u64>,
+        price: u64,
+        match_info: &mut MatchInfo,
+    ) {
+        // only called once `breaches_band` found both the band and a reference price
+        let band = self.price_band.unwrap();
+        match band.action {
+            BandAction::Halt => self.phase = TradingPhase::Halted,
+            BandAction::VolatilityAuction => {
+                self.phase = TradingPhase::VolatilityAuction;
+                self.begin_auction();
+            }
+        }
+        let breach = BandBreach {
+            order_id: order.order_id,
+            reference_price: reference_price.unwrap(),
+            price,
+            phase: self.phase,
+        };
+        match_info.band_breach = Some(breach);
+        self.market_data
+            .publish(MarketDataEvent::PriceBandBreached { breach });
+    }
+
+    /// Matches the order against a single price level, sharing its quantity among the
+    /// resting orders with the book's matching algorithm. Returns whether self-trade
+    /// prevention cancelled the rest of the order.
+    fn match_level(
+        &mut self,
+        best_price: u64,
+        mut queue: VecDeque<Order>,
+        order: &mut Order,
+        match_info: &mut MatchInfo,
+    ) -> bool {
+        let mut cancelled = false;
+        while !order.is_filled() && !queue.is_empty() && !cancelled {
+            let displayed: Vec<u64> = queue.iter().map(|order| order.visible_quantity).collect();
+            let allocations = self.matching.allocate(&displayed, order.remaining_quantity);
+            let mut cancelled_makers = Vec::new();
+            let mut progressed = false;
+
+            for (maker_order, allocation) in queue.iter_mut().zip(allocations) {
+                if order.is_filled() {
+                    break;
+                }
+                if allocation == 0 {
+                    continue;
+                }
+                progressed = true;
+
+                let self_trade = order.owner.is_some() && order.owner == maker_order.owner;
+                if let (true, Some(mode)) = (self_trade, self.self_trade_prevention) {
+                    let (cancel_taker, cancel_maker) =
+                        Self::prevent_self_trade(mode, best_price, order, maker_order, match_info);
+                    if cancel_maker {
+                        cancelled_makers.push(maker_order.order_id);
+                    } else if mode == SelfTradePrevention::Decrement {
+                        self.market_data.publish(MarketDataEvent::OrderModified {
+                            order_id: maker_order.order_id,
+                            side: maker_order.side,
+                            price: best_price,
+                            quantity: maker_order.visible_quantity,
+                        });
+                    }
+                    // the allocations of this round counted the prevented quantity, so
+                    // the level is allocated again without it
+                    cancelled = cancel_taker;
+                    break;
+                }
+
+                let trade_quantity = min(allocation, order.remaining_quantity);
+                // can unwrap as quantity will necessarily be leq than both order's quantity
+                maker_order.fill(trade_quantity).unwrap();
+                order.fill(trade_quantity).unwrap();
+                let trade = Trade::new(
+                    order.order_id,
+                    maker_order.order_id,
+                    best_price,
+                    trade_quantity,
+                );
+                match_info.trade_log.push(trade);
+                self.notify(|listener| listener.on_trade(&trade));
+                match_info.reports.push(ExecutionReport::fill(
+                    order.order_id,
+                    Liquidity::Taker,
+                    best_price,
+                    trade_quantity,
+                    order.remaining_quantity,
+                ));
+                match_info.reports.push(ExecutionReport::fill(
+                    maker_order.order_id,
+                    Liquidity::Maker,
+                    best_price,
+                    trade_quantity,
+                    maker_order.remaining_quantity,
+                ));
+                self.market_data.publish(MarketDataEvent::OrderExecuted {
+                    order_id: maker_order.order_id,
+                    side: maker_order.side,
+                    price: best_price,
+                    quantity: trade_quantity,
+                    taker_order_id: order.order_id,
+                });
+                self.last_trade_price = Some(best_price);
+            }
+
+            // the refilled slices join the back of the queue like new orders would
+            let mut level = VecDeque::with_capacity(queue.len());
+            let mut refilled = Vec::new();
+            for mut maker_order in queue.drain(..) {
+                let cancelled_maker = cancelled_makers.contains(&maker_order.order_id);
+                if maker_order.is_filled() || cancelled_maker {
+                    self.forget_order(&maker_order);
+                    if cancelled_maker {
+                        self.notify(|listener| listener.on_order_cancelled(&maker_order));
+                    }
+                    self.market_data.publish(MarketDataEvent::OrderDeleted {
+                        order_id: maker_order.order_id,
+                        side: maker_order.side,
+                        price: best_price,
+                    });
+                } else if maker_order.needs_replenishing() {
+                    maker_order.replenish();
+                    // losing priority is published as the order leaving and rejoining the level
+                    self.market_data.publish(MarketDataEvent::OrderDeleted {
+                        order_id: maker_order.order_id,
+                        side: maker_order.side,
+                        price: best_price,
+                    });
+                    refilled.push(maker_order);
+                } else {
+                    level.push_back(maker_order);
+                }
+            }
+            for maker_order in refilled {
+                match_info.refills.push(Refill {
+                    order_id: maker_order.order_id,
+                    price: best_price,
+                    displayed_quantity: maker_order.visible_quantity,
+                    remaining_quantity: maker_order.remaining_quantity,
+                    queue_position: level.len(),
+                });
+                self.market_data.publish(MarketDataEvent::OrderAdded {
+                    order_id: maker_order.order_id,
+                    side: maker_order.side,
+                    price: best_price,
+                    quantity: maker_order.visible_quantity,
+                });
+                level.push_back(maker_order);
+            }
+            queue = level;
+
+            if !progressed {
+                break;
+            }
+        }
+
+        let maker_side = match order.side {
+            Side::Ask => Side::Bid,
+            Side::Bid => Side::Ask,
+        };
+        if !queue.is_empty() {
+            let book_side = match maker_side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            book_side.insert(best_price, queue);
+        }
+        self.publish_level(maker_side, best_price);
+        cancelled
+    }
+
+    /// Checks that the current phase accepts new orders and modifications.
+    /// Cancellations are accepted in every phase.
+    fn check_phase(&self) -> Result<(), LivreError> {
+        match self.phase {
+            TradingPhase::Halted => Err(LivreError::TradingHalted),
+            phase if !phase.accepts_orders() => Err(LivreError::MarketClosed),
+            _ => Ok(()),
+        }
+    }
+
+    /// Trades `volume` at `price` between the best bids and asks, front of the queue
+    /// first.
+    fn execute_uncross(&mut self, price: u64, mut volume: u64, match_info: &mut MatchInfo) {
+        let mut touched_levels = Vec::new();
+        while volume > 0 {
+            // the volume never exceeds the quantity crossing at the price, so both
+            // sides still have an order
+            let (Some(mut bid_level), Some(mut ask_level)) =
+                (self.bids.last_entry(), self.asks.first_entry())
+            else {
+                break;
+            };
+            let (bid_price, ask_price) = (*bid_level.key(), *ask_level.key());
+            // prevented self trades can leave less quantity crossing than indicated
+            if bid_price < price || ask_price > price {
+                break;
+            }
+            for level in [(Side::Bid, bid_price), (Side::Ask, ask_price)] {
+                if !touched_levels.contains(&level) {
+                    touched_levels.push(level);
+                }
+            }
+            let bid = bid_level.get_mut().front_mut().unwrap();
+            let ask = ask_level.get_mut().front_mut().unwrap();
+
+            let self_trade = bid.owner.is_some() && bid.owner == ask.owner;
+            if let (true, Some(mode)) = (self_trade, self.self_trade_prevention) {
+                let bid_quantity = bid.remaining_quantity;
+                let (cancel_bid, cancel_ask) =
+                    Self::prevent_self_trade(mode, price, bid, ask, match_info);
+                // the bid stands in for the incoming order, whose cancelled rest is
+                // reported by the caller in continuous matching
+                if cancel_bid && mode != SelfTradePrevention::Decrement {
+                    match_info.reports.push(ExecutionReport::Cancelled {
+                        order_id: bid.order_id,
+                        quantity: bid_quantity,
+                        reason: CancelReason::SelfTradePrevention,
+                    });
+                }
+                let mut cancelled = Vec::new();
+                for (side, mut level, cancel) in [
+                    (Side::Bid, bid_level, cancel_bid),
+                    (Side::Ask, ask_level, cancel_ask),
+                ] {
+                    if cancel {
+                        cancelled.push(level.get_mut().pop_front().unwrap());
+                        if level.get().is_empty() {
+                            level.remove();
+                        }
+                    } else if mode == SelfTradePrevention::Decrement {
+                        let order = level.get().front().unwrap();
+                        self.market_data.publish(MarketDataEvent::OrderModified {
+                            order_id: order.order_id,
+                            side,
+                            price: order.price,
+                            quantity: order.visible_quantity,
+                        });
+                    }
+                }
+                for order in cancelled {
+                    self.forget_order(&order);
+                    self.market_data.publish(MarketDataEvent::OrderDeleted {
+                        order_id: order.order_id,
+                        side: order.side,
+                        price: order.price,
+                    });
+                    self.notify(|listener| listener.on_order_cancelled(&order));
+                }
+                continue;
+            }
+
+            let quantity = min(volume, min(bid.remaining_quantity, ask.remaining_quantity));
+            bid.fill(quantity).unwrap();
+            ask.fill(quantity).unwrap();
+            volume -= quantity;
+            let trade = Trade::new(bid.order_id, ask.order_id, price, quantity);
+            match_info.trade_log.push(trade);
+            match_info.reports.push(ExecutionReport::fill(
+                bid.order_id,
+                Liquidity::Taker,
+                price,
+                quantity,
+                bid.remaining_quantity,
+            ));
+            match_info.reports.push(ExecutionReport::fill(
+                ask.order_id,
+                Liquidity::Maker,
+                price,
+                quantity,
+                ask.remaining_quantity,
+            ));
+
+            let mut done = Vec::new();
+            for (side, mut level) in [(Side::Bid, bid_level), (Side::Ask, ask_level)] {
+                let order = level.get().front().unwrap();
+                self.market_data.publish(MarketDataEvent::OrderExecuted {
+                    order_id: order.order_id,
+                    side,
+                    price,
+                    quantity,
+                    taker_order_id: trade.taker_order_id,
+                });
+                if order.is_filled() {
+                    done.push(level.get_mut().pop_front().unwrap());
+                    if level.get().is_empty() {
+                        level.remove();
+                    }
+                }
+            }
+            for order in done {
+                self.forget_order(&order);
+                self.market_data.publish(MarketDataEvent::OrderDeleted {
+                    order_id: order.order_id,
+                    side: order.side,
+                    price: order.price,
+                });
+            }
+            self.notify(|listener| listener.on_trade(&trade));
+            self.last_trade_price = Some(price);
+        }
+
+        // only the order at the front of each side can have been partially filled
+        for (side, level_price) in touched_levels.iter().copied() {
+            let book_side = match side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            };
+            let Some(level) = book_side.get_mut(&level_price) else {
+                continue;
+            };
+            if !level.front().is_some_and(Order::needs_replenishing) {
+                continue;
+            }
+            let mut order = level.pop_front().unwrap();
+            order.replenish();
+            match_info.refills.push(Refill {
+                order_id: order.order_id,
+                price: level_price,
+                displayed_quantity: order.visible_quantity,
+                remaining_quantity: order.remaining_quantity,
+                queue_position: level.len(),
+            });
+            let (order_id, quantity) = (order.order_id, order.visible_quantity);
+            level.push_back(order);
+            self.market_data.publish(MarketDataEvent::OrderDeleted {
+                order_id,
+                side,
+                price: level_price,
+            });
+            self.market_data.publish(MarketDataEvent::OrderAdded {
+                order_id,
+                side,
+                price: level_price,
+                quantity,
+            });
+        }
+        for (side, level_price) in touched_levels {
+            self.publish_level(side, level_price);
+        }
+    }
+
+    fn publish_indication(&mut self) {
+        if self.auction && self.market_data.is_enabled() {
+            let indication = self.indicative_uncross();
+            self.market_data
+                .publish(MarketDataEvent::IndicativeUncross { indication });
+        }
+    }
+
+    fn publish_level(&mut self, side: Side, price: u64) {
+        if !self.market_data.is_enabled() && self.listeners.is_empty() {
+            return;
+        }
+        let book_side = match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        };
+        let (quantity, order_count) = book_side.get(&price).map_or((0, 0), |queue| {
+            (
+                queue.iter().map(Order::displayed_quantity).sum(),
+                queue.len(),
+            )
+        });
+        self.market_data.publish(MarketDataEvent::LevelUpdated {
+            side,
+            price,
+            quantity,
+            order_count,
+        });
+        let level = PriceLevel {
+            price,
+            quantity,
+            order_count,
+        };
+        self.notify(|listener| listener.on_level_changed(side, &level));
+    }
+
+    fn notify(&mut self, mut notification: impl FnMut(&mut dyn OrderbookListener)) {
+        for listener in &mut self.listeners {
+            notification(listener.as_mut());
+        }
+    }
+
+    /// Applies the self-trade prevention mode to a pair of orders with the same owner,
+    /// returning whether the taker and the maker should be cancelled.
+    fn prevent_self_trade(
+        mode: SelfTradePrevention,
+        price: u64,
+        order: &mut Order,
+        maker_order: &mut Order,
+        match_info: &mut MatchInfo,
+    ) -> (bool, bool) {
+        let (taker_quantity, maker_quantity) = match mode {
+            SelfTradePrevention::CancelNewest => (order.remaining_quantity, 0),
+            SelfTradePrevention::CancelOldest => (0, maker_order.remaining_quantity),
+            SelfTradePrevention::CancelBoth => {
+                (order.remaining_quantity, maker_order.remaining_quantity)
+            }
+            SelfTradePrevention::Decrement => {
+                let quantity = min(order.remaining_quantity, maker_order.remaining_quantity);
+                order.decrement(quantity);
+                maker_order.decrement(quantity);
+                (quantity, quantity)
+            }
+        };
+        match_info.prevented_trades.push(PreventedTrade {
+            taker_order_id: order.order_id,
+            maker_order_id: maker_order.order_id,
+            price,
+            taker_quantity,
+            maker_quantity,
+            mode,
+        });
+        // decremented orders with nothing left are gone just like cancelled ones, the
+        // cancelled rest of the taker is reported once it has finished matching
+        let decremented = mode == SelfTradePrevention::Decrement;
+        let cancel_maker = matches!(
+            mode,
+            SelfTradePrevention::CancelOldest | SelfTradePrevention::CancelBoth
+        ) || (decremented && maker_order.is_filled());
+        if decremented && order.is_filled() {
+            match_info.reports.push(ExecutionReport::Cancelled {
+                order_id: order.order_id,
+                quantity: taker_quantity,
+                reason: CancelReason::SelfTradePrevention,
+            });
+        }
+        if cancel_maker {
+            match_info.reports.push(ExecutionReport::Cancelled {
+                order_id: maker_order.order_id,
+                quantity: maker_quantity,
+                reason: CancelReason::SelfTradePrevention,
+            });
+        }
+        (
+            matches!(
+                mode,
+                SelfTradePrevention::CancelNewest | SelfTradePrevention::CancelBoth
+            ) || (decremented && order.is_filled()),
+            cancel_maker,
+        )
+    }
+
+    /// Drops an order that has left its price level from the lookup indexes.
+    fn forget_order(&mut self, order: &Order) {
+        self.orders.remove(&order.order_id);
+        if let Some(expiry) = order.order_type.expiry() {
+            self.expiries.remove(&(expiry, order.order_id));
+        }
+    }
+
+    /// Iterates over the levels of one side of the book, best price first.
+    fn levels(&self, side: Side) -> Box<dyn Iterator<Item = (&u64, &VecDeque<Order>)> + '_> {
+        match side {
+            Side::Ask => Box::new(self.asks.iter()),
+            Side::Bid => Box::new(self.bids.iter().rev()),
+        }
+    }
+
+    fn can_match(&self, side: Side, price: u64) -> bool {
+        match side {
+            Side::Ask => {
+                if let Some((&best_price, _)) = self.bids.last_key_value() {
+                    best_price >= price
+                } else {
+                    false
+                }
+            }
+            Side::Bid => {
+                if let Some((&best_price, _)) = self.asks.first_key_value() {
+                    best_price <= price
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn market_limit_price(&self, side: Side) -> Option<u64> {
+        match side {
+            Side::Ask => self.bids.last_key_value().map(|(&best_price, _)| {
+                self.market_protection
+                    .map_or(0, |protection| best_price.saturating_sub(protection))
+            }),
+            Side::Bid => self.asks.first_key_value().map(|(&best_price, _)| {
+                self.market_protection
+                    .map_or(u64::MAX, |protection| best_price.saturating_add(protection))
+            }),
+        }
+    }
+
+    /// The most aggressive price one tick away from the best opposite price.
+    fn slide_price(&self, side: Side) -> Option<u64> {
+        match side {
+            Side::Ask => self
+                .bids
+                .last_key_value()
+                .and_then(|(&best_price, _)| self.instrument.price_above(best_price)),
+            Side::Bid => self
+                .asks
+                .first_key_value()
+                .and_then(|(&best_price, _)| self.instrument.price_below(best_price)),
+        }
+    }
+
+    fn can_fully_fill(&self, price: u64, mut quantity: u64, side: Side) -> bool {
+        if !self.can_match(side, price) {
+            return false;
+        }
+
+        let level_iter: Box<dyn Iterator<Item = _>> = match side {
+            Side::Ask => Box::new(self.bids.iter().rev()),
+            Side::Bid => Box::new(self.asks.iter()),
+        };
+        for (&level_price, queue) in level_iter {
+            if (side == Side::Ask && level_price < price)
+                || (side == Side::Bid && level_price > price)
+            {
+                return false;
+            }
+            // matching stops at the first level outside the price band
+            if self.breaches_band(self.last_trade_price, level_price) {
+                return false;
+            }
+            let level_quantity = queue.iter().map(|order| order.remaining_quantity).sum();
+            if quantity <= level_quantity {
+                return true;
+            }
+            quantity -= level_quantity;
+        }
+        false
+    }
+}
+
+impl<M: MatchingAlgorithm + Default> Default for Orderbook<M> {
+    fn default() -> Self {
+        Self::with_algorithm(M::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use matching::ProRata;
+    use std::sync::{Arc, Mutex};
+
+    /// Records the ids of the orders a book reports as cancelled.
+    #[derive(Clone, Default)]
+    struct CancelRecorder(Arc<Mutex<Vec<u64>>>);
+
+    impl OrderbookListener for CancelRecorder {
+        fn on_order_cancelled(&mut self, order: &Order) {
+            self.0.lock().unwrap().push(order.order_id());
+        }
+    }
+
+    impl CancelRecorder {
+        fn cancelled(&self) -> Vec<u64> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[test]
+    fn stop_order_triggers_on_a_print_through_its_stop_price() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 105, 5))
+            .unwrap();
+        let stop = Order::new(
+            OrderType::StopLimit { stop_price: 100 },
+            3,
+            Side::Bid,
+            105,
+            5,
+        );
+        book.add_order(stop).unwrap();
+        assert_eq!(book.stop_order_count(), 1);
+
+        let match_info = book
+            .add_order(Order::new(OrderType::FillAndKill, 4, Side::Bid, 100, 5))
+            .unwrap();
+        assert_eq!(match_info.triggered_orders, vec![3]);
+        let trade = &match_info.triggered_trade_log[0];
+        assert_eq!(
+            (trade.taker_order_id, trade.maker_order_id, trade.price),
+            (3, 2, 105)
+        );
+        assert_eq!(book.stop_order_count(), 0);
+        assert_eq!(book.order_count(), 0);
+    }
+
+    #[test]
+    fn stop_limit_rests_at_its_limit_price_once_triggered() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 5))
+            .unwrap();
+        let stop = Order::new(
+            OrderType::StopLimit { stop_price: 100 },
+            2,
+            Side::Ask,
+            98,
+            5,
+        );
+        book.add_order(stop).unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 100, 5))
+            .unwrap();
+        assert_eq!(match_info.triggered_orders, vec![2]);
+        assert!(match_info.triggered_trade_log.is_empty());
+        assert_eq!(book.stop_order_count(), 0);
+        assert_eq!(book.order_count(), 1);
+        assert_eq!(book.asks[&98][0].order_id, 2);
+    }
+
+    #[test]
+    fn stop_orders_can_be_cancelled_before_they_trigger() {
+        let mut book = Orderbook::new();
+        let stop = Order::new(
+            OrderType::StopMarket { stop_price: 100 },
+            1,
+            Side::Bid,
+            0,
+            5,
+        );
+        book.add_order(stop).unwrap();
+        let stop = Order::new(OrderType::StopMarket { stop_price: 90 }, 1, Side::Ask, 0, 5);
+        assert!(matches!(
+            book.add_order(stop),
+            Err(LivreError::DuplicateOrderId)
+        ));
+
+        assert_eq!(book.cancel_order(1).unwrap().order_id, 1);
+        assert_eq!(book.stop_order_count(), 0);
+    }
+
+    #[test]
+    fn iceberg_with_zero_display_quantity_is_rejected() {
+        let mut book = Orderbook::new();
+        let iceberg =
+            Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10).with_display_quantity(0);
+        assert!(matches!(
+            book.add_order(iceberg),
+            Err(LivreError::ZeroDisplayQuantity)
+        ));
+        assert_eq!(book.order_count(), 0);
+    }
+
+    #[test]
+    fn iceberg_refills_from_reserve_and_loses_priority() {
+        let mut book = Orderbook::new();
+        let iceberg =
+            Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10).with_display_quantity(4);
+        book.add_order(iceberg).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 100, 5))
+            .unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::FillAndKill, 3, Side::Bid, 100, 4))
+            .unwrap();
+        assert_eq!(match_info.trade_log.len(), 1);
+        assert_eq!(match_info.trade_log[0].maker_order_id, 1);
+        assert_eq!(match_info.trade_log[0].quantity, 4);
+        assert_eq!(match_info.refills.len(), 1);
+        assert_eq!(match_info.refills[0].displayed_quantity, 4);
+        assert_eq!(match_info.refills[0].remaining_quantity, 6);
+        assert_eq!(match_info.refills[0].queue_position, 1);
+
+        let level: Vec<_> = book.asks[&100]
+            .iter()
+            .map(|order| (order.order_id, order.displayed_quantity()))
+            .collect();
+        assert_eq!(level, vec![(2, 5), (1, 4)]);
+    }
+
+    #[test]
+    fn iceberg_hidden_quantity_trades_within_one_order() {
+        let mut book = Orderbook::new();
+        let iceberg =
+            Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10).with_display_quantity(3);
+        book.add_order(iceberg).unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 10))
+            .unwrap();
+        let traded: u64 = match_info
+            .trade_log
+            .iter()
+            .map(|trade| trade.quantity)
+            .sum();
+        assert_eq!(traded, 10);
+        assert!(matches!(match_info.order_state, OrderState::Filled));
+        assert_eq!(book.order_count(), 0);
+    }
+
+    #[test]
+    fn post_only_order_that_would_cross_is_rejected() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10))
+            .unwrap();
+        let post_only = Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 5)
+            .with_post_only(PostOnly::Reject);
+        assert!(matches!(
+            book.add_order(post_only),
+            Err(LivreError::PostOnlyWouldCross)
+        ));
+
+        let post_only = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 99, 5)
+            .with_post_only(PostOnly::Reject);
+        let match_info = book.add_order(post_only).unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(book.bids.keys().collect::<Vec<_>>(), vec![&99]);
+    }
+
+    #[test]
+    fn sliding_post_only_order_rests_one_tick_inside_the_opposite_side() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 10))
+            .unwrap();
+        let post_only = Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 90, 5)
+            .with_post_only(PostOnly::Slide);
+        let match_info = book.add_order(post_only).unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(book.asks[&101][0].order_id, 2);
+    }
+
+    #[test]
+    fn rejected_modification_leaves_the_original_order_in_place() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 90, 10))
+            .unwrap();
+        let post_only = Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 110, 10)
+            .with_post_only(PostOnly::Reject);
+        book.add_order(post_only).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 110, 5))
+            .unwrap();
+
+        assert!(matches!(
+            book.modify_order(ModifyOrder {
+                order_id: 2,
+                side: Side::Ask,
+                price: 90,
+                quantity: 10
+            }),
+            Err(LivreError::PostOnlyWouldCross)
+        ));
+        let level: Vec<_> = book.asks[&110]
+            .iter()
+            .map(|order| (order.order_id, order.remaining_quantity))
+            .collect();
+        assert_eq!(level, vec![(2, 10), (3, 5)]);
+        assert_eq!(book.order_count(), 3);
+    }
+
+    #[test]
+    fn market_order_sweeps_levels_and_cancels_its_remainder() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 150, 5))
+            .unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::Market, 3, Side::Bid, 0, 12))
+            .unwrap();
+        let trades: Vec<_> = match_info
+            .trade_log
+            .iter()
+            .map(|trade| (trade.maker_order_id, trade.price, trade.quantity))
+            .collect();
+        assert_eq!(trades, vec![(1, 100, 5), (2, 150, 5)]);
+        assert_eq!(match_info.cancelled_quantity, 2);
+        assert!(matches!(
+            match_info.order_state,
+            OrderState::PartialFill(10)
+        ));
+        assert_eq!(book.order_count(), 0);
+    }
+
+    #[test]
+    fn market_protection_limits_how_far_a_market_order_sweeps() {
+        let mut book = Orderbook::new();
+        book.set_market_protection(Some(10));
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 89, 5))
+            .unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::Market, 3, Side::Ask, 0, 10))
+            .unwrap();
+        assert_eq!(match_info.trade_log.len(), 1);
+        assert_eq!(match_info.trade_log[0].price, 100);
+        assert_eq!(match_info.cancelled_quantity, 5);
+        assert_eq!(book.bids.keys().collect::<Vec<_>>(), vec![&89]);
+    }
+
+    #[test]
+    fn market_order_into_an_empty_side_is_unfillable() {
+        let mut book = Orderbook::new();
+        assert!(matches!(
+            book.add_order(Order::new(OrderType::Market, 1, Side::Bid, 0, 1)),
+            Err(LivreError::UnfillableOrder)
+        ));
+    }
+
+    #[test]
+    fn fill_or_kill_checks_the_levels_it_would_sweep() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 101, 5))
+            .unwrap();
+        assert!(matches!(
+            book.add_order(Order::new(OrderType::FillOrKill, 3, Side::Bid, 100, 6)),
+            Err(LivreError::UnfillableOrder)
+        ));
+        let match_info = book
+            .add_order(Order::new(OrderType::FillOrKill, 4, Side::Bid, 101, 10))
+            .unwrap();
+        assert!(matches!(match_info.order_state, OrderState::Filled));
+        assert_eq!(book.order_count(), 0);
+    }
+
+    #[test]
+    fn good_for_day_orders_expire_at_the_session_end() {
+        let clock = ManualClock::new(1_000);
+        let mut book = Orderbook::with_clock(clock.clone());
+        book.set_session_end(Some(5_000));
+        book.add_order(Order::new(OrderType::GoodForDay, 1, Side::Bid, 100, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 99, 10))
+            .unwrap();
+
+        clock.set(4_999);
+        assert!(book.expire_orders().is_empty());
+        clock.set(5_000);
+        let expired = book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order.order_id, 1);
+        assert_eq!(expired[0].expired_at, 5_000);
+        assert_eq!(book.order_count(), 1);
+
+        // the end of day routine runs once per session end
+        book.add_order(Order::new(OrderType::GoodForDay, 3, Side::Bid, 98, 10))
+            .unwrap();
+        clock.advance(1_000);
+        assert!(book.expire_orders().is_empty());
+        assert_eq!(book.order_count(), 2);
+    }
+
+    #[test]
+    fn good_till_date_orders_expire_in_expiry_order() {
+        let clock = ManualClock::new(0);
+        let mut book = Orderbook::with_clock(clock.clone());
+        for (order_id, expiry) in [(1, 300), (2, 100), (3, 200), (4, 100)] {
+            let order_type = OrderType::GoodTillDate { expiry };
+            book.add_order(Order::new(order_type, order_id, Side::Ask, 100, 1))
+                .unwrap();
+        }
+        book.cancel_order(3).unwrap();
+
+        clock.set(250);
+        let expired: Vec<_> = book
+            .expire_orders()
+            .into_iter()
+            .map(|expired| (expired.order.order_id, expired.expired_at))
+            .collect();
+        assert_eq!(expired, vec![(2, 100), (4, 100)]);
+        assert_eq!(book.order_count(), 1);
+
+        clock.set(300);
+        assert_eq!(book.expire_orders().len(), 1);
+        assert_eq!(book.order_count(), 0);
+    }
+
+    #[test]
+    fn good_till_date_order_already_expired_is_rejected() {
+        let clock = ManualClock::new(500);
+        let mut book = Orderbook::with_clock(clock);
+        let order_type = OrderType::GoodTillDate { expiry: 500 };
+        assert!(matches!(
+            book.add_order(Order::new(order_type, 1, Side::Bid, 100, 1)),
+            Err(LivreError::OrderExpired)
+        ));
+    }
+
+    #[test]
+    fn filled_good_till_date_order_leaves_the_expiry_index() {
+        let clock = ManualClock::new(0);
+        let mut book = Orderbook::with_clock(clock.clone());
+        let order_type = OrderType::GoodTillDate { expiry: 100 };
+        book.add_order(Order::new(order_type, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(order_type, 2, Side::Ask, 101, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::FillAndKill, 3, Side::Bid, 100, 5))
+            .unwrap();
+        assert_eq!(book.expiries.len(), 1);
+
+        clock.set(100);
+        let expired = book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order.order_id, 2);
+    }
+
+    #[test]
+    fn modified_good_till_date_order_keeps_its_expiry() {
+        let clock = ManualClock::new(0);
+        let mut book = Orderbook::with_clock(clock.clone());
+        let order_type = OrderType::GoodTillDate { expiry: 100 };
+        book.add_order(Order::new(order_type, 1, Side::Bid, 100, 5))
+            .unwrap();
+        book.modify_order(ModifyOrder {
+            order_id: 1,
+            side: Side::Bid,
+            price: 99,
+            quantity: 7,
+        })
+        .unwrap();
+        assert_eq!(book.expiries.iter().collect::<Vec<_>>(), vec![&(100, 1)]);
+
+        clock.set(100);
+        assert_eq!(book.expire_orders().len(), 1);
+        assert_eq!(book.order_count(), 0);
+    }
+
+    fn book_with_own_ask(mode: SelfTradePrevention) -> Orderbook {
+        let mut book = Orderbook::new();
+        book.set_self_trade_prevention(Some(mode));
+        let own_ask = Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5).with_owner(7);
+        book.add_order(own_ask).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 100, 5))
+            .unwrap();
+        book
+    }
+
+    #[test]
+    fn cancel_newest_cancels_the_incoming_order() {
+        let mut book = book_with_own_ask(SelfTradePrevention::CancelNewest);
+        let taker = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 8).with_owner(7);
+        let match_info = book.add_order(taker).unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(match_info.cancelled_quantity, 8);
+        assert_eq!(match_info.prevented_trades.len(), 1);
+        assert_eq!(book.order_count(), 2);
+    }
+
+    #[test]
+    fn cancel_oldest_cancels_the_resting_order_and_keeps_matching() {
+        let mut book = book_with_own_ask(SelfTradePrevention::CancelOldest);
+        let taker = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 8).with_owner(7);
+        let match_info = book.add_order(taker).unwrap();
+        assert_eq!(match_info.trade_log.len(), 1);
+        assert_eq!(match_info.trade_log[0].maker_order_id, 2);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.bids[&100][0].remaining_quantity, 3);
+    }
+
+    #[test]
+    fn cancel_both_cancels_both_orders() {
+        let mut book = book_with_own_ask(SelfTradePrevention::CancelBoth);
+        let taker = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 8).with_owner(7);
+        let match_info = book.add_order(taker).unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(match_info.cancelled_quantity, 8);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.order_count(), 1);
+    }
+
+    #[test]
+    fn decrement_reduces_both_orders_without_trading() {
+        let mut book = book_with_own_ask(SelfTradePrevention::Decrement);
+        let taker = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 8).with_owner(7);
+        let match_info = book.add_order(taker).unwrap();
+        assert_eq!(match_info.prevented_trades[0].maker_quantity, 5);
+        assert_eq!(match_info.trade_log.len(), 1);
+        assert_eq!(match_info.trade_log[0].maker_order_id, 2);
+        assert_eq!(match_info.trade_log[0].quantity, 3);
+        assert!(!book.orders.contains_key(&1));
+        assert_eq!(book.asks[&100][0].remaining_quantity, 2);
+    }
+
+    #[test]
+    fn orders_of_different_owners_trade_normally() {
+        let mut book = book_with_own_ask(SelfTradePrevention::CancelNewest);
+        let taker = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 5).with_owner(8);
+        let match_info = book.add_order(taker).unwrap();
+        assert_eq!(match_info.trade_log.len(), 1);
+        assert_eq!(match_info.trade_log[0].maker_order_id, 1);
+        assert!(match_info.prevented_trades.is_empty());
+    }
+
+    #[test]
+    fn prevented_self_trade_reallocates_the_level() {
+        let mut book = Orderbook::with_algorithm(ProRata);
+        book.set_self_trade_prevention(Some(SelfTradePrevention::CancelOldest));
+        let own_ask = Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10).with_owner(7);
+        book.add_order(own_ask).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 100, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 100, 30))
+            .unwrap();
+
+        let taker = Order::new(OrderType::FillAndKill, 4, Side::Bid, 100, 20).with_owner(7);
+        let match_info = book.add_order(taker).unwrap();
+        let fills: Vec<_> = match_info
+            .trade_log
+            .iter()
+            .map(|trade| (trade.maker_order_id, trade.quantity))
+            .collect();
+        assert_eq!(fills, vec![(2, 5), (3, 15)]);
+        assert!(!book.orders.contains_key(&1));
+    }
+
+    /// Breaks the allocation contract by never allocating anything.
+    struct Stalled;
+
+    impl MatchingAlgorithm for Stalled {
+        fn allocate(&self, resting: &[u64], _quantity: u64) -> Vec<u64> {
+            vec![0; resting.len()]
+        }
+    }
+
+    #[test]
+    fn matching_stops_at_a_level_that_allocates_nothing() {
+        let mut book = Orderbook::with_algorithm(Stalled);
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 101, 5))
+            .unwrap();
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 101, 5))
+            .unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(book.asks.len(), 2);
+    }
+
+    #[test]
+    fn depth_aggregates_displayed_quantity_best_price_first() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 99, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 5))
+            .unwrap();
+        let iceberg =
+            Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 20).with_display_quantity(2);
+        book.add_order(iceberg).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 4, Side::Ask, 102, 1))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Ask, 101, 3))
+            .unwrap();
+
+        let depth = book.depth(1);
+        assert_eq!(
+            depth.bids,
+            vec![PriceLevel {
+                price: 100,
+                quantity: 7,
+                order_count: 2,
+            }]
+        );
+        assert_eq!(depth.asks.len(), 1);
+        let asks: Vec<_> = book
+            .side_depth(Side::Ask, 5)
+            .iter()
+            .map(|level| level.price)
+            .collect();
+        assert_eq!(asks, vec![101, 102]);
+        assert_eq!((book.best_bid(), book.best_ask()), (Some(100), Some(101)));
+        assert_eq!(book.spread(), Some(1));
+    }
+
+    #[test]
+    fn empty_book_has_no_best_prices_or_spread() {
+        let book = Orderbook::new();
+        assert_eq!(book.depth(10), DepthSnapshot::default());
+        assert_eq!(
+            (book.best_bid(), book.best_ask(), book.spread()),
+            (None, None, None)
+        );
+    }
+
+    #[test]
+    fn orders_can_be_looked_up_with_their_queue_position() {
+        let mut book = Orderbook::new();
+        for order_id in 1..=3 {
+            book.add_order(Order::new(
+                OrderType::GoodTillCancel,
+                order_id,
+                Side::Ask,
+                100,
+                order_id,
+            ))
+            .unwrap();
+        }
+        book.add_order(Order::new(OrderType::FillAndKill, 4, Side::Bid, 100, 2))
+            .unwrap();
+
+        let order = book.get_order(2).unwrap();
+        assert_eq!(order.queue_position, 0);
+        assert_eq!(order.order_state, OrderState::PartialFill(1));
+        assert_eq!(book.get_order(1), None);
+        let stop = Order::new(OrderType::StopMarket { stop_price: 90 }, 5, Side::Ask, 0, 1);
+        book.add_order(stop).unwrap();
+        assert_eq!(book.get_order(5), None);
+    }
+
+    #[test]
+    fn side_orders_iterate_best_price_first_in_priority_order() {
+        let mut book = Orderbook::new();
+        for (order_id, price) in [(1, 99), (2, 100), (3, 99), (4, 100)] {
+            book.add_order(Order::new(
+                OrderType::GoodTillCancel,
+                order_id,
+                Side::Bid,
+                price,
+                1,
+            ))
+            .unwrap();
+        }
+        let orders: Vec<_> = book
+            .side_orders(Side::Bid)
+            .map(|order| (order.order_id, order.queue_position))
+            .collect();
+        assert_eq!(orders, vec![(2, 0), (4, 1), (1, 0), (3, 1)]);
+        let level: Vec<_> = book
+            .level_orders(Side::Bid, 99)
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(level, vec![1, 3]);
+        assert_eq!(book.level_orders(Side::Ask, 99).count(), 0);
+    }
+
+    #[test]
+    fn process_reports_the_lifecycle_of_every_order_touched() {
+        let mut book = Orderbook::new();
+        let reports = book
+            .process(Command::Add(Order::new(
+                OrderType::GoodTillCancel,
+                1,
+                Side::Ask,
+                100,
+                10,
+            )))
+            .unwrap();
+        assert_eq!(
+            reports,
+            vec![
+                ExecutionReport::Accepted {
+                    order_id: 1,
+                    side: Side::Ask,
+                    price: 100,
+                    quantity: 10
+                },
+                ExecutionReport::Rested {
+                    order_id: 1,
+                    side: Side::Ask,
+                    price: 100,
+                    remaining_quantity: 10
+                },
+            ]
+        );
+
+        let reports = book
+            .process(Command::Add(Order::new(
+                OrderType::FillAndKill,
+                2,
+                Side::Bid,
+                100,
+                15,
+            )))
+            .unwrap();
+        assert_eq!(
+            reports,
+            vec![
+                ExecutionReport::Accepted {
+                    order_id: 2,
+                    side: Side::Bid,
+                    price: 100,
+                    quantity: 15
+                },
+                ExecutionReport::PartiallyFilled {
+                    order_id: 2,
+                    liquidity: Liquidity::Taker,
+                    price: 100,
+                    quantity: 10,
+                    remaining_quantity: 5
+                },
+                ExecutionReport::Filled {
+                    order_id: 1,
+                    liquidity: Liquidity::Maker,
+                    price: 100,
+                    quantity: 10
+                },
+                ExecutionReport::Cancelled {
+                    order_id: 2,
+                    quantity: 5,
+                    reason: CancelReason::Unfilled
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn process_reports_cancels_expiries_and_rejects() {
+        let clock = ManualClock::new(0);
+        let mut book = Orderbook::with_clock(clock.clone());
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 99, 5))
+            .unwrap();
+        book.add_order(Order::new(
+            OrderType::GoodTillDate { expiry: 10 },
+            2,
+            Side::Bid,
+            98,
+            3,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            book.process(Command::Cancel(1)).unwrap(),
+            vec![ExecutionReport::Cancelled {
+                order_id: 1,
+                quantity: 5,
+                reason: CancelReason::Requested
+            }]
+        );
+        assert_eq!(
+            book.process(Command::Cancel(1)).unwrap(),
+            vec![ExecutionReport::Rejected {
+                order_id: 1,
+                reason: LivreError::OrderNotFound
+            }]
+        );
+        assert_eq!(
+            book.process(Command::Add(Order::new(
+                OrderType::GoodTillCancel,
+                2,
+                Side::Ask,
+                101,
+                1
+            )))
+            .unwrap(),
+            vec![ExecutionReport::Rejected {
+                order_id: 2,
+                reason: LivreError::DuplicateOrderId
+            }]
+        );
+
+        clock.set(10);
+        assert_eq!(
+            book.process(Command::Expire).unwrap(),
+            vec![ExecutionReport::Expired {
+                order_id: 2,
+                quantity: 3,
+                expired_at: 10
+            }]
+        );
+    }
+
+    #[test]
+    fn process_reports_replacements_and_triggers() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(
+            OrderType::StopMarket { stop_price: 100 },
+            2,
+            Side::Bid,
+            0,
+            2,
+        ))
+        .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 98, 1))
+            .unwrap();
+
+        let reports = book
+            .process(Command::Modify(ModifyOrder::new(3, Side::Bid, 100, 1)))
+            .unwrap();
+        assert_eq!(
+            reports[0],
+            ExecutionReport::Replaced {
+                order_id: 3,
+                side: Side::Bid,
+                price: 100,
+                quantity: 1
+            }
+        );
+        assert!(reports.contains(&ExecutionReport::Triggered { order_id: 2 }));
+        assert_eq!(
+            reports.last(),
+            Some(&ExecutionReport::PartiallyFilled {
+                order_id: 1,
+                liquidity: Liquidity::Maker,
+                price: 100,
+                quantity: 2,
+                remaining_quantity: 2
+            })
+        );
+    }
+
+    #[test]
+    fn modified_stop_orders_are_reported_as_replaced() {
+        let mut book = Orderbook::new();
+        book.enable_market_data();
+        book.add_order(Order::new(
+            OrderType::StopLimit { stop_price: 105 },
+            1,
+            Side::Bid,
+            106,
+            5,
+        ))
+        .unwrap();
+        let reports = book
+            .process(Command::Modify(ModifyOrder::new(1, Side::Bid, 107, 4)))
+            .unwrap();
+        assert_eq!(
+            reports,
+            vec![ExecutionReport::Replaced {
+                order_id: 1,
+                side: Side::Bid,
+                price: 107,
+                quantity: 4
+            }]
+        );
+        assert_eq!(book.stop_order_count(), 1);
+        assert!(book.drain_market_data().is_empty());
+    }
+
+    #[test]
+    fn triggered_stop_with_nothing_to_trade_is_cancelled() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 10))
+            .unwrap();
+        let stop = Order::new(
+            OrderType::StopMarket { stop_price: 100 },
+            2,
+            Side::Ask,
+            0,
+            5,
+        );
+        book.add_order(stop).unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 100, 10))
+            .unwrap();
+        assert_eq!(match_info.triggered_orders, vec![2]);
+        assert_eq!(
+            match_info.reports.last(),
+            Some(&ExecutionReport::Cancelled {
+                order_id: 2,
+                quantity: 5,
+                reason: CancelReason::Unfilled,
+            })
+        );
+        assert_eq!(book.stop_order_count(), 0);
+    }
+
+    #[test]
+    fn triggered_post_only_stop_that_would_cross_is_rejected() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 99, 10))
+            .unwrap();
+        let stop = Order::new(
+            OrderType::StopLimit { stop_price: 100 },
+            3,
+            Side::Ask,
+            99,
+            5,
+        )
+        .with_post_only(PostOnly::Reject);
+        book.add_order(stop).unwrap();
+
+        let match_info = book
+            .add_order(Order::new(OrderType::FillAndKill, 4, Side::Ask, 100, 10))
+            .unwrap();
+        assert_eq!(
+            match_info.reports.last(),
+            Some(&ExecutionReport::Rejected {
+                order_id: 3,
+                reason: LivreError::PostOnlyWouldCross,
+            })
+        );
+        assert_eq!(book.get_order(3), None);
+    }
+
+    #[test]
+    fn listeners_hear_of_every_order_leaving_without_a_fill() {
+        let mut book = Orderbook::new();
+        let recorder = CancelRecorder::default();
+        book.add_listener(recorder.clone());
+        book.set_self_trade_prevention(Some(SelfTradePrevention::CancelOldest));
+
+        let own_order = Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5).with_owner(7);
+        book.add_order(own_order).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 101, 5))
+            .unwrap();
+        let taker = Order::new(OrderType::FillAndKill, 3, Side::Bid, 101, 8).with_owner(7);
+        book.add_order(taker).unwrap();
+        assert_eq!(recorder.cancelled(), vec![1, 3]);
+
+        book.cancel_order(2).unwrap_err();
+        let stop = Order::new(
+            OrderType::StopMarket { stop_price: 100 },
+            4,
+            Side::Ask,
+            0,
+            5,
+        );
+        book.add_order(stop).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Bid, 100, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::FillAndKill, 6, Side::Ask, 100, 5))
+            .unwrap();
+        // the stop triggers on the trade but has nothing left to sell into
+        assert_eq!(recorder.cancelled(), vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn decrement_notifies_the_orders_it_reduces_to_nothing() {
+        let mut book = Orderbook::new();
+        let recorder = CancelRecorder::default();
+        book.add_listener(recorder.clone());
+        book.set_self_trade_prevention(Some(SelfTradePrevention::Decrement));
+
+        let maker = Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5).with_owner(7);
+        book.add_order(maker).unwrap();
+        let taker = Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 3).with_owner(7);
+        let match_info = book.add_order(taker).unwrap();
+        assert!(match_info.trade_log.is_empty());
+        assert_eq!(recorder.cancelled(), vec![2]);
+        assert_eq!(book.get_order(1).unwrap().remaining_quantity, 2);
+
+        let taker = Order::new(OrderType::GoodTillCancel, 3, Side::Bid, 100, 4).with_owner(7);
+        book.add_order(taker).unwrap();
+        assert_eq!(recorder.cancelled(), vec![2, 1]);
+        assert_eq!(book.get_order(3).unwrap().remaining_quantity, 2);
+    }
+
+    fn auction_with_own_orders(mode: Option<SelfTradePrevention>) -> (Orderbook, UncrossResult) {
+        let mut book = Orderbook::new();
+        book.set_self_trade_prevention(mode);
+        book.set_phase(TradingPhase::ClosingAuction).unwrap();
+        let own_bid = Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 101, 10).with_owner(7);
+        book.add_order(own_bid).unwrap();
+        let own_ask = Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 99, 6).with_owner(7);
+        book.add_order(own_ask).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 100, 6))
+            .unwrap();
+        let uncross = book.uncross().unwrap();
+        (book, uncross)
+    }
+
+    #[test]
+    fn auction_trades_between_own_orders_without_prevention() {
+        let (_, uncross) = auction_with_own_orders(None);
+        assert_eq!(
+            uncross.trade_log,
+            vec![Trade::new(1, 2, 100, 6), Trade::new(1, 3, 100, 4)]
+        );
+    }
+
+    #[test]
+    fn auction_applies_self_trade_prevention() {
+        let (book, uncross) = auction_with_own_orders(Some(SelfTradePrevention::CancelOldest));
+        assert_eq!(uncross.trade_log, vec![Trade::new(1, 3, 100, 6)]);
+        assert!(uncross.reports.contains(&ExecutionReport::Cancelled {
+            order_id: 2,
+            quantity: 6,
+            reason: CancelReason::SelfTradePrevention,
+        }));
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.get_order(1).unwrap().remaining_quantity, 4);
+
+        let (book, uncross) = auction_with_own_orders(Some(SelfTradePrevention::CancelNewest));
+        assert!(uncross.trade_log.is_empty());
+        assert_eq!(book.get_order(1), None);
+        assert_eq!(book.order_count(), 2);
+
+        let (book, uncross) = auction_with_own_orders(Some(SelfTradePrevention::Decrement));
+        assert_eq!(uncross.trade_log, vec![Trade::new(1, 3, 100, 4)]);
+        assert_eq!(book.get_order(2), None);
+        assert_eq!(book.get_order(3).unwrap().remaining_quantity, 2);
+    }
+
+    #[test]
+    fn uncross_after_resuming_a_halted_auction_ends_the_phase() {
+        let mut book = Orderbook::new();
+        book.set_phase(TradingPhase::Halted).unwrap();
+        book.set_phase(TradingPhase::OpeningAuction).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 101, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 99, 10))
+            .unwrap();
+
+        let uncross = book.uncross().unwrap();
+        assert_eq!(uncross.trade_log.len(), 1);
+        assert_eq!(book.phase(), TradingPhase::Continuous);
+        assert!(!book.in_auction());
+    }
+
+    #[test]
+    fn start_auction_moves_a_continuous_session_into_an_auction_phase() {
+        let mut book = Orderbook::new();
+        book.start_auction().unwrap();
+        assert_eq!(book.phase(), TradingPhase::VolatilityAuction);
+        assert!(book.in_auction());
+
+        book.set_phase(TradingPhase::Halted).unwrap();
+        assert_eq!(
+            book.uncross().err(),
+            Some(LivreError::InvalidPhaseTransition)
+        );
+        book.set_phase(TradingPhase::Closed).unwrap();
+        assert_eq!(
+            book.start_auction(),
+            Err(LivreError::InvalidPhaseTransition)
+        );
+        assert!(!book.in_auction());
+    }
+
+    #[test]
+    fn uncrossing_the_closing_auction_closes_the_session() {
+        let mut book = Orderbook::new();
+        book.set_phase(TradingPhase::ClosingAuction).unwrap();
+        book.uncross().unwrap();
+        assert_eq!(book.phase(), TradingPhase::Closed);
+        assert_eq!(
+            book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 1))
+                .err(),
+            Some(LivreError::MarketClosed)
+        );
+    }
+
+    #[test]
+    fn process_returns_refused_session_commands_as_errors() {
+        let mut book = Orderbook::new();
+        assert_eq!(
+            book.process(Command::Uncross).err(),
+            Some(LivreError::InvalidPhaseTransition)
+        );
+        assert_eq!(
+            book.process(Command::SetPhase(TradingPhase::OpeningAuction)),
+            Err(LivreError::InvalidPhaseTransition)
+        );
+        book.process(Command::SetPhase(TradingPhase::Closed))
+            .unwrap();
+        assert_eq!(
+            book.process(Command::StartAuction),
+            Err(LivreError::InvalidPhaseTransition)
+        );
+        assert_eq!(book.phase(), TradingPhase::Closed);
     }
 }
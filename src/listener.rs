@@ -0,0 +1,131 @@
+use crate::{Order, PriceLevel, Side, Trade};
+
+/// Receives push notifications as an `Orderbook` changes. Every method does nothing
+/// by default, so listeners only implement what they care about.
+///
+/// Listeners are called synchronously from the book's mutations in the order the
+/// changes happen, after the change has been applied.
+pub trait OrderbookListener: Send {
+    /// A new order passed validation, before it is matched. Stop orders are
+    /// notified when they enter the trigger book.
+    fn on_order_accepted(&mut self, _order: &Order) {}
+
+    fn on_trade(&mut self, _trade: &Trade) {}
+
+    /// An order left the book without being completely filled: through
+    /// `Orderbook::cancel_order`, when its time in force ran out, to self-trade
+    /// prevention, or because the rest of an order that cannot rest in the book was
+    /// cancelled. Triggered stop orders that cannot enter the book are notified
+    /// here as well.
+    fn on_order_cancelled(&mut self, _order: &Order) {}
+
+    /// The replacement of an order passed validation, before it is matched.
+    fn on_order_modified(&mut self, _order: &Order) {}
+
+    /// The aggregated state of a price level after a change. A level with an order
+    /// count of zero has been removed from the book.
+    fn on_level_changed(&mut self, _side: Side, _level: &PriceLevel) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ModifyOrder, OrderType, Orderbook};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct EventLog(Arc<Mutex<Vec<String>>>);
+
+    impl EventLog {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+
+        fn push(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl OrderbookListener for EventLog {
+        fn on_order_accepted(&mut self, order: &Order) {
+            self.push(format!("accepted {}", order.order_id()));
+        }
+
+        fn on_trade(&mut self, trade: &Trade) {
+            self.push(format!(
+                "trade {} x {} @ {}",
+                trade.taker_order_id, trade.maker_order_id, trade.price
+            ));
+        }
+
+        fn on_order_cancelled(&mut self, order: &Order) {
+            self.push(format!("cancelled {}", order.order_id()));
+        }
+
+        fn on_order_modified(&mut self, order: &Order) {
+            self.push(format!("modified {}", order.order_id()));
+        }
+
+        fn on_level_changed(&mut self, side: Side, level: &PriceLevel) {
+            self.push(format!(
+                "{side:?} {} {}x{}",
+                level.price, level.quantity, level.order_count
+            ));
+        }
+    }
+
+    #[test]
+    fn listeners_see_changes_in_the_order_they_happen() {
+        let mut book = Orderbook::new();
+        let log = EventLog::default();
+        book.add_listener(log.clone());
+
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 100, 5))
+            .unwrap();
+        assert_eq!(
+            log.take(),
+            ["accepted 1", "Ask 100 10x1", "accepted 2", "Ask 100 15x2"]
+        );
+
+        book.modify_order(ModifyOrder::new(2, Side::Ask, 101, 5))
+            .unwrap();
+        assert_eq!(log.take(), ["modified 2", "Ask 101 5x1", "Ask 100 10x1"]);
+
+        book.add_order(Order::new(OrderType::FillAndKill, 3, Side::Bid, 101, 12))
+            .unwrap();
+        assert_eq!(
+            log.take(),
+            [
+                "accepted 3",
+                "trade 3 x 1 @ 100",
+                "Ask 100 0x0",
+                "trade 3 x 2 @ 101",
+                "Ask 101 3x1"
+            ]
+        );
+
+        book.cancel_order(2).unwrap();
+        assert_eq!(log.take(), ["cancelled 2", "Ask 101 0x0"]);
+    }
+
+    #[test]
+    fn modifying_a_stop_order_is_not_a_new_acceptance() {
+        let mut book = Orderbook::new();
+        let log = EventLog::default();
+        book.add_listener(log.clone());
+
+        let stop = Order::new(
+            OrderType::StopLimit { stop_price: 105 },
+            1,
+            Side::Bid,
+            106,
+            5,
+        );
+        book.add_order(stop).unwrap();
+        book.modify_order(ModifyOrder::new(1, Side::Bid, 107, 5))
+            .unwrap();
+        assert_eq!(log.take(), ["accepted 1", "modified 1"]);
+    }
+}
@@ -1,14 +1,88 @@
-use livre::{Order, Orderbook};
+use livre::{
+    batch::{self, InputFormat},
+    repl::Repl,
+    server::Server,
+    Orderbook,
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    path::Path,
+    process::ExitCode,
+};
 
-fn main() {
-    let mut order_book = Orderbook::new();
-    let bid1 = Order::new(livre::OrderType::Market, 1, livre::Side::Bid, 150, 10);
-    if let Ok(inf) = order_book.add_order(bid1) {
-        println!("{:?}", inf.order_state);
+const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+
+const USAGE: &str = "\
+usage: livre serve [address]
+       livre repl [script]
+       livre batch <orders.csv|orders.ndjson> <output directory>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("serve") => serve(args.get(1).map_or(DEFAULT_ADDR, String::as_str)),
+        Some("repl") => repl(args.get(1).map(String::as_str)),
+        Some("batch") if args.len() == 3 => run_batch(&args[1], &args[2]),
+        Some(command) => {
+            eprintln!("unknown command {command}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+        None => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
     }
-    let ask1 = Order::new(livre::OrderType::Market, 2, livre::Side::Ask, 150, 5);
-    match order_book.add_order(ask1) {
-        Ok(inf) => println!("{:?}", inf.order_state),
-        Err(err) => println!("{}", err),
+}
+
+fn serve(addr: &str) -> io::Result<()> {
+    let server = Server::bind(addr)?;
+    println!("listening on {}", server.local_addr()?);
+    server.run(Orderbook::new())
+}
+
+fn repl(script: Option<&str>) -> io::Result<()> {
+    let mut repl = Repl::new(Orderbook::new());
+    match script {
+        Some(path) => repl.run(BufReader::new(File::open(path)?), io::stdout(), false),
+        None => repl.run(io::stdin().lock(), io::stdout(), true),
     }
 }
+
+fn run_batch(input: &str, output: &str) -> io::Result<()> {
+    let format = InputFormat::from_path(input).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot tell the format of {input} from its extension"),
+        )
+    })?;
+    let output = Path::new(output);
+    fs::create_dir_all(output)?;
+    let create = |name: &str| File::create(output.join(name)).map(BufWriter::new);
+
+    let mut book = Orderbook::new();
+    let summary = batch::run_batch(
+        &mut book,
+        BufReader::new(File::open(input)?),
+        format,
+        create("trades.csv")?,
+        create("rejects.csv")?,
+    )?;
+    batch::write_book(&book, create("book.csv")?)?;
+    println!(
+        "{} records, {} trades, {} rejects, {} orders resting",
+        summary.records,
+        summary.trades,
+        summary.rejects,
+        book.order_count()
+    );
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use crate::{auction::AuctionIndication, phase::BandBreach, Side};
+
+/// A change to the visible book, in the style of an ITCH feed. Quantities are the
+/// displayed quantities, so iceberg reserves are never revealed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketDataEvent {
+    /// An order joined the back of its price level.
+    OrderAdded {
+        order_id: u64,
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+    /// A resting order was replaced, either by `Orderbook::modify_order` or by
+    /// self-trade prevention decrementing it. Replaced orders lose their priority.
+    OrderModified {
+        order_id: u64,
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+    /// An order left the book, because it was cancelled, expired or fully executed.
+    OrderDeleted {
+        order_id: u64,
+        side: Side,
+        price: u64,
+    },
+    /// A resting order traded against an incoming order.
+    OrderExecuted {
+        order_id: u64,
+        side: Side,
+        price: u64,
+        quantity: u64,
+        taker_order_id: u64,
+    },
+    /// The aggregated state of a price level after a change. A level with an order
+    /// count of zero has been removed from the book.
+    LevelUpdated {
+        side: Side,
+        price: u64,
+        quantity: u64,
+        order_count: usize,
+    },
+    /// The price and volume a call auction would uncross at, published after every
+    /// change to the book during the auction. `None` while the book is not crossed.
+    IndicativeUncross {
+        indication: Option<AuctionIndication>,
+    },
+    /// An order tried to trade outside the price band, halting the book or
+    /// starting a volatility auction.
+    PriceBandBreached { breach: BandBreach },
+}
+
+/// A published event with its sequence number. Sequence numbers start at 1 and
+/// increase by one per message, so a subscriber can detect gaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarketDataMessage {
+    pub sequence: u64,
+    pub event: MarketDataEvent,
+}
+
+/// Buffers the market data produced by an `Orderbook` until it is drained.
+/// Publishing is disabled until `enable` is called, so books nobody listens to
+/// don't accumulate messages.
+#[derive(Debug, Default)]
+pub struct MarketDataPublisher {
+    enabled: bool,
+    last_sequence: u64,
+    messages: Vec<MarketDataMessage>,
+}
+
+impl MarketDataPublisher {
+    pub fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    pub fn disable(&mut self) {
+        self.enabled = false;
+        self.messages.clear();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Sequence number of the last published message, 0 if none were published.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    /// Takes every message published since the last drain.
+    pub fn drain(&mut self) -> Vec<MarketDataMessage> {
+        std::mem::take(&mut self.messages)
+    }
+
+    pub(crate) fn set_last_sequence(&mut self, last_sequence: u64) {
+        self.last_sequence = last_sequence;
+    }
+
+    pub(crate) fn publish(&mut self, event: MarketDataEvent) {
+        if !self.enabled {
+            return;
+        }
+        self.last_sequence += 1;
+        self.messages.push(MarketDataMessage {
+            sequence: self.last_sequence,
+            event,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ModifyOrder, Order, OrderType, Orderbook, PostOnly};
+
+    fn events(book: &mut Orderbook) -> Vec<MarketDataEvent> {
+        book.drain_market_data()
+            .into_iter()
+            .map(|message| message.event)
+            .collect()
+    }
+
+    #[test]
+    fn nothing_is_published_until_enabled() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 5))
+            .unwrap();
+        assert!(book.drain_market_data().is_empty());
+        assert_eq!(book.market_data().last_sequence(), 0);
+    }
+
+    #[test]
+    fn resting_and_executing_orders_publish_order_and_level_events() {
+        let mut book = Orderbook::new();
+        book.enable_market_data();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        assert_eq!(
+            events(&mut book),
+            vec![
+                MarketDataEvent::OrderAdded {
+                    order_id: 1,
+                    side: Side::Ask,
+                    price: 100,
+                    quantity: 5,
+                },
+                MarketDataEvent::LevelUpdated {
+                    side: Side::Ask,
+                    price: 100,
+                    quantity: 5,
+                    order_count: 1,
+                },
+            ]
+        );
+
+        book.add_order(Order::new(OrderType::FillAndKill, 2, Side::Bid, 100, 5))
+            .unwrap();
+        assert_eq!(
+            events(&mut book),
+            vec![
+                MarketDataEvent::OrderExecuted {
+                    order_id: 1,
+                    side: Side::Ask,
+                    price: 100,
+                    quantity: 5,
+                    taker_order_id: 2,
+                },
+                MarketDataEvent::OrderDeleted {
+                    order_id: 1,
+                    side: Side::Ask,
+                    price: 100,
+                },
+                MarketDataEvent::LevelUpdated {
+                    side: Side::Ask,
+                    price: 100,
+                    quantity: 0,
+                    order_count: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn sequence_numbers_are_gapless_across_drains() {
+        let mut book = Orderbook::new();
+        book.enable_market_data();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 100, 5))
+            .unwrap();
+        let first = book.drain_market_data();
+        book.cancel_order(1).unwrap();
+        let second = book.drain_market_data();
+
+        let sequences: Vec<_> = first
+            .iter()
+            .chain(&second)
+            .map(|message| message.sequence)
+            .collect();
+        assert_eq!(sequences, (1..=4).collect::<Vec<_>>());
+        assert_eq!(book.market_data().last_sequence(), 4);
+    }
+
+    #[test]
+    fn modified_orders_are_published_as_modified_and_rejected_ones_not_at_all() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 5))
+            .unwrap();
+        book.enable_market_data();
+        book.modify_order(ModifyOrder::new(1, Side::Ask, 101, 4))
+            .unwrap();
+        assert_eq!(
+            events(&mut book),
+            vec![
+                MarketDataEvent::OrderModified {
+                    order_id: 1,
+                    side: Side::Ask,
+                    price: 101,
+                    quantity: 4,
+                },
+                MarketDataEvent::LevelUpdated {
+                    side: Side::Ask,
+                    price: 101,
+                    quantity: 4,
+                    order_count: 1,
+                },
+                MarketDataEvent::LevelUpdated {
+                    side: Side::Ask,
+                    price: 100,
+                    quantity: 0,
+                    order_count: 0,
+                },
+            ]
+        );
+
+        let order = Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 99, 5)
+            .with_post_only(PostOnly::Reject);
+        book.add_order(order).unwrap();
+        events(&mut book);
+        assert!(book
+            .modify_order(ModifyOrder::new(2, Side::Bid, 101, 5))
+            .is_err());
+        assert!(events(&mut book).is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use std::cmp::min;
+
+/// Decides how an incoming order's quantity is shared among the orders resting at a
+/// single price level.
+pub trait MatchingAlgorithm {
+    /// `resting` holds the displayed quantity of every order at the level in queue
+    /// order. Returns the quantity allocated to each of them, which must not exceed
+    /// their displayed quantity and must add up to `min(quantity, resting.sum())`.
+    fn allocate(&self, resting: &[u64], quantity: u64) -> Vec<u64>;
+}
+
+/// Price-time priority: orders at a level are filled one after another in the order
+/// they arrived.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PriceTimePriority;
+
+impl MatchingAlgorithm for PriceTimePriority {
+    fn allocate(&self, resting: &[u64], quantity: u64) -> Vec<u64> {
+        let mut allocations = vec![0; resting.len()];
+        fifo(resting, quantity, &mut allocations);
+        allocations
+    }
+}
+
+/// Orders at a level are filled in proportion to their displayed quantity. Lots
+/// lost to rounding go to the earliest orders.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProRata;
+
+impl MatchingAlgorithm for ProRata {
+    fn allocate(&self, resting: &[u64], quantity: u64) -> Vec<u64> {
+        let mut allocations = vec![0; resting.len()];
+        pro_rata(resting, quantity, &mut allocations);
+        allocations
+    }
+}
+
+/// The order at the front of the level is filled first, the rest of the quantity is
+/// shared pro rata among the other orders.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TopOrderProRata;
+
+impl MatchingAlgorithm for TopOrderProRata {
+    fn allocate(&self, resting: &[u64], quantity: u64) -> Vec<u64> {
+        let mut allocations = vec![0; resting.len()];
+        if let Some(&top_quantity) = resting.first() {
+            allocations[0] = min(top_quantity, quantity);
+            pro_rata(resting, quantity - allocations[0], &mut allocations);
+        }
+        allocations
+    }
+}
+
+/// `fifo_percentage` percent of the incoming quantity is allocated by price-time
+/// priority, the rest pro rata.
+#[derive(Debug, Clone, Copy)]
+pub struct SplitFifoProRata {
+    pub fifo_percentage: u8,
+}
+
+impl MatchingAlgorithm for SplitFifoProRata {
+    fn allocate(&self, resting: &[u64], quantity: u64) -> Vec<u64> {
+        let mut allocations = vec![0; resting.len()];
+        let fifo_quantity =
+            (quantity as u128 * min(self.fifo_percentage, 100) as u128 / 100) as u64;
+        let allocated = fifo(resting, fifo_quantity, &mut allocations);
+        pro_rata(resting, quantity - allocated, &mut allocations);
+        allocations
+    }
+}
+
+/// Allocates up to `quantity` front to back on top of the existing allocations,
+/// returning how much was allocated.
+fn fifo(resting: &[u64], mut quantity: u64, allocations: &mut [u64]) -> u64 {
+    let mut allocated = 0;
+    for (allocation, &resting_quantity) in allocations.iter_mut().zip(resting) {
+        let fill = min(resting_quantity - *allocation, quantity);
+        *allocation += fill;
+        quantity -= fill;
+        allocated += fill;
+    }
+    allocated
+}
+
+/// Allocates up to `quantity` in proportion to what each order has left after the
+/// existing allocations, handing rounding leftovers out front to back.
+fn pro_rata(resting: &[u64], quantity: u64, allocations: &mut [u64]) {
+    let available: Vec<u64> = resting
+        .iter()
+        .zip(allocations.iter())
+        .map(|(&resting_quantity, &allocation)| resting_quantity - allocation)
+        .collect();
+    let total: u64 = available.iter().sum();
+    if total <= quantity {
+        for (allocation, available) in allocations.iter_mut().zip(available) {
+            *allocation += available;
+        }
+        return;
+    }
+
+    let mut allocated = 0;
+    for (allocation, &available) in allocations.iter_mut().zip(&available) {
+        // widen to avoid overflowing on large quantities
+        let share = (quantity as u128 * available as u128 / total as u128) as u64;
+        *allocation += share;
+        allocated += share;
+    }
+    fifo(resting, quantity - allocated, allocations);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Order, OrderType, Orderbook, Side};
+
+    #[test]
+    fn price_time_priority_fills_front_to_back() {
+        assert_eq!(PriceTimePriority.allocate(&[4, 6, 5], 7), vec![4, 3, 0]);
+        assert_eq!(PriceTimePriority.allocate(&[4, 6], 20), vec![4, 6]);
+    }
+
+    #[test]
+    fn pro_rata_shares_by_size_with_leftovers_to_the_front() {
+        assert_eq!(ProRata.allocate(&[10, 30, 60], 50), vec![5, 15, 30]);
+        // shares of 3.33 round down and the leftover lot goes to the first order
+        assert_eq!(ProRata.allocate(&[10, 10, 10], 10), vec![4, 3, 3]);
+        assert_eq!(ProRata.allocate(&[1, 2], 5), vec![1, 2]);
+    }
+
+    #[test]
+    fn top_order_pro_rata_fills_the_front_order_first() {
+        assert_eq!(
+            TopOrderProRata.allocate(&[10, 20, 20], 30),
+            vec![10, 10, 10]
+        );
+        assert_eq!(TopOrderProRata.allocate(&[10, 20, 20], 6), vec![6, 0, 0]);
+    }
+
+    #[test]
+    fn split_fifo_pro_rata_divides_the_quantity() {
+        let split = SplitFifoProRata {
+            fifo_percentage: 40,
+        };
+        // 4 by price-time to the first order, then 6 pro rata over the 6 and 10 left
+        assert_eq!(split.allocate(&[10, 10], 10), vec![7, 3]);
+    }
+
+    #[test]
+    fn orderbook_allocates_levels_with_its_algorithm() {
+        let mut book = Orderbook::with_algorithm(ProRata);
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Ask, 100, 30))
+            .unwrap();
+        let match_info = book
+            .add_order(Order::new(OrderType::FillAndKill, 3, Side::Bid, 100, 20))
+            .unwrap();
+        let fills: Vec<_> = match_info
+            .trade_log
+            .iter()
+            .map(|trade| (trade.maker_order_id, trade.quantity))
+            .collect();
+        assert_eq!(fills, vec![(1, 5), (2, 15)]);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{auction::UncrossResult, clock::Timestamp};
+
+/// The phases of a trading session, which decide what an `Orderbook` accepts:
+///
+/// | Phase | New orders | Modify | Cancel | Matching |
+/// |---|---|---|---|---|
+/// | `PreOpen` | no | no | yes | none |
+/// | `OpeningAuction` | resting types | yes | yes | call auction |
+/// | `Continuous` | all types | yes | yes | continuous |
+/// | `Halted` | no | no | yes | none |
+/// | `ClosingAuction` | resting types | yes | yes | call auction |
+/// | `VolatilityAuction` | resting types | yes | yes | call auction |
+/// | `Closed` | no | no | yes | none |
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TradingPhase {
+    PreOpen,
+    OpeningAuction,
+    #[default]
+    Continuous,
+    Halted,
+    ClosingAuction,
+    /// An unscheduled call auction started by a price band breach or by
+    /// `Orderbook::start_auction`, ended by moving back to continuous trading.
+    VolatilityAuction,
+    Closed,
+}
+
+impl TradingPhase {
+    pub fn is_auction(self) -> bool {
+        matches!(
+            self,
+            TradingPhase::OpeningAuction
+                | TradingPhase::ClosingAuction
+                | TradingPhase::VolatilityAuction
+        )
+    }
+
+    /// Whether new orders and modifications are accepted.
+    pub fn accepts_orders(self) -> bool {
+        matches!(
+            self,
+            TradingPhase::OpeningAuction
+                | TradingPhase::Continuous
+                | TradingPhase::ClosingAuction
+                | TradingPhase::VolatilityAuction
+        )
+    }
+
+    /// Whether the session may move from this phase to `next`. Any open phase may
+    /// be halted or closed, and a halted session resumes through continuous trading
+    /// or a reopening auction.
+    pub fn can_transition(self, next: TradingPhase) -> bool {
+        use TradingPhase::*;
+        match (self, next) {
+            (Closed, next) => matches!(next, PreOpen | OpeningAuction),
+            (_, Halted | Closed) => true,
+            (PreOpen, next) => matches!(next, OpeningAuction | Continuous),
+            (OpeningAuction, next) => next == Continuous,
+            (Continuous, next) => matches!(next, ClosingAuction | VolatilityAuction),
+            (Halted, next) => matches!(
+                next,
+                OpeningAuction | Continuous | ClosingAuction | VolatilityAuction
+            ),
+            (VolatilityAuction, next) => next == Continuous,
+            (ClosingAuction, _) => false,
+        }
+    }
+}
+
+/// A phase transition made by `Orderbook::update_phase`.
+#[derive(Debug)]
+pub struct PhaseChange {
+    pub phase: TradingPhase,
+    /// The scheduled time of the transition.
+    pub scheduled_at: Timestamp,
+    /// The trades of the auction the transition ended, if it ended one.
+    pub uncross: Option<UncrossResult>,
+}
+
+/// What a book does when an order would trade outside its price band.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BandAction {
+    Halt,
+    VolatilityAuction,
+}
+
+/// A dynamic price band around the last traded price. Before the first trade the
+/// band is inactive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceBand {
+    /// Half width of the band in basis points of the reference price.
+    pub width_bps: u64,
+    pub action: BandAction,
+}
+
+impl PriceBand {
+    pub fn contains(&self, reference_price: u64, price: u64) -> bool {
+        let width = (reference_price as u128 * self.width_bps as u128 / 10_000) as u64;
+        price >= reference_price.saturating_sub(width)
+            && price <= reference_price.saturating_add(width)
+    }
+}
+
+/// An order that tried to trade outside the price band, stopping continuous
+/// trading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BandBreach {
+    pub order_id: u64,
+    pub reference_price: u64,
+    /// The price of the level the order would have traded at next.
+    pub price: u64,
+    /// The phase the book moved to, either `Halted` or `VolatilityAuction`.
+    pub phase: TradingPhase,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::ManualClock, report::CancelReason, ExecutionReport, LivreError, Order, OrderType,
+        Orderbook, Side,
+    };
+    use TradingPhase::*;
+
+    #[test]
+    fn transitions_follow_the_session_lifecycle() {
+        assert!(PreOpen.can_transition(OpeningAuction));
+        assert!(OpeningAuction.can_transition(Continuous));
+        assert!(Continuous.can_transition(ClosingAuction));
+        assert!(ClosingAuction.can_transition(Closed));
+        assert!(Closed.can_transition(PreOpen));
+        assert!(Continuous.can_transition(Halted));
+        assert!(Halted.can_transition(VolatilityAuction));
+        assert!(VolatilityAuction.can_transition(Continuous));
+
+        assert!(!Closed.can_transition(Continuous));
+        assert!(!ClosingAuction.can_transition(Continuous));
+        assert!(!OpeningAuction.can_transition(ClosingAuction));
+        assert!(!Continuous.can_transition(PreOpen));
+    }
+
+    #[test]
+    fn schedules_must_be_ordered_valid_transitions() {
+        let mut book = Orderbook::new();
+        book.set_phase(Closed).unwrap();
+        assert_eq!(
+            book.set_schedule(vec![(100, Continuous)]),
+            Err(LivreError::InvalidPhaseTransition)
+        );
+        assert_eq!(
+            book.set_schedule(vec![(200, PreOpen), (100, OpeningAuction)]),
+            Err(LivreError::InvalidPhaseTransition)
+        );
+        assert_eq!(
+            book.set_phase(Continuous).err(),
+            Some(LivreError::InvalidPhaseTransition)
+        );
+    }
+
+    #[test]
+    fn scheduled_session_runs_on_the_clock() {
+        let clock = ManualClock::new(0);
+        let mut book = Orderbook::with_clock(clock.clone());
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 90, 1))
+            .unwrap();
+        book.set_phase(Closed).unwrap();
+        book.set_schedule(vec![
+            (100, PreOpen),
+            (200, OpeningAuction),
+            (300, Continuous),
+        ])
+        .unwrap();
+
+        assert!(book.update_phase().is_empty());
+        clock.set(150);
+        let changes = book.update_phase();
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].phase, changes[0].scheduled_at), (PreOpen, 100));
+        assert_eq!(
+            book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 101, 5))
+                .err(),
+            Some(LivreError::MarketClosed)
+        );
+        // cancels are accepted in every phase
+        book.cancel_order(1).unwrap();
+
+        clock.set(200);
+        book.update_phase();
+        assert!(book.in_auction());
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 101, 5))
+            .unwrap();
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 100, 5))
+            .unwrap();
+        assert!(match_info.trade_log.is_empty());
+
+        clock.set(300);
+        let changes = book.update_phase();
+        assert_eq!(changes[0].phase, Continuous);
+        let uncross = changes[0].uncross.as_ref().unwrap();
+        assert_eq!(uncross.trade_log.len(), 1);
+        assert_eq!(book.phase(), Continuous);
+        assert!(!book.in_auction());
+    }
+
+    #[test]
+    fn halted_books_refuse_orders_but_accept_cancels() {
+        let mut book = Orderbook::new();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Bid, 99, 1))
+            .unwrap();
+        book.set_phase(Halted).unwrap();
+        assert_eq!(
+            book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 99, 1))
+                .err(),
+            Some(LivreError::TradingHalted)
+        );
+        book.cancel_order(1).unwrap();
+        book.set_phase(Continuous).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 99, 1))
+            .unwrap();
+    }
+
+    fn banded_book(action: BandAction) -> Orderbook {
+        let mut book = Orderbook::new();
+        book.set_price_band(Some(PriceBand {
+            width_bps: 500,
+            action,
+        }));
+        // the band is inactive until the first trade sets a reference price
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 1))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 200, 1))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 104, 5))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 4, Side::Ask, 110, 5))
+            .unwrap();
+        book
+    }
+
+    #[test]
+    fn band_contains_prices_within_its_width() {
+        let band = PriceBand {
+            width_bps: 100,
+            action: BandAction::Halt,
+        };
+        assert!(band.contains(1_000, 990));
+        assert!(band.contains(1_000, 1_010));
+        assert!(!band.contains(1_000, 989));
+        assert!(!band.contains(1_000, 1_011));
+        assert!(band.contains(10, 10));
+        assert!(!band.contains(10, 11));
+    }
+
+    #[test]
+    fn band_breach_halts_trading() {
+        let mut book = banded_book(BandAction::Halt);
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Bid, 110, 10))
+            .unwrap();
+        assert_eq!(
+            match_info.band_breach,
+            Some(BandBreach {
+                order_id: 5,
+                reference_price: 100,
+                price: 110,
+                phase: Halted,
+            })
+        );
+        assert_eq!(
+            match_info.reports.last(),
+            Some(&ExecutionReport::Cancelled {
+                order_id: 5,
+                quantity: 5,
+                reason: CancelReason::CircuitBreaker,
+            })
+        );
+        assert_eq!(book.phase(), Halted);
+        assert_eq!(book.best_ask(), Some(110));
+    }
+
+    #[test]
+    fn band_breach_can_start_a_volatility_auction() {
+        let mut book = banded_book(BandAction::VolatilityAuction);
+        let match_info = book
+            .add_order(Order::new(OrderType::GoodTillCancel, 5, Side::Bid, 110, 10))
+            .unwrap();
+        assert_eq!(match_info.band_breach.unwrap().phase, VolatilityAuction);
+        // the rest of the order waits for the auction instead of being cancelled
+        assert_eq!(book.phase(), VolatilityAuction);
+        assert!(book.in_auction());
+        assert_eq!(book.get_order(5).unwrap().remaining_quantity, 5);
+
+        let uncross = book.set_phase(Continuous).unwrap().unwrap();
+        assert_eq!(uncross.indication.unwrap().price, 110);
+        assert_eq!(uncross.trade_log.len(), 1);
+    }
+
+    #[test]
+    fn fill_or_kill_orders_do_not_count_liquidity_outside_the_band() {
+        let mut book = banded_book(BandAction::Halt);
+        let fill_or_kill = Order::new(OrderType::FillOrKill, 5, Side::Bid, 110, 10);
+        assert_eq!(
+            book.add_order(fill_or_kill).err(),
+            Some(LivreError::UnfillableOrder)
+        );
+        assert_eq!(book.phase(), Continuous);
+        assert_eq!(book.depth(2).asks[0].quantity, 5);
+    }
+}
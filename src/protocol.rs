@@ -0,0 +1,282 @@
+//! A line based text protocol for driving an `Orderbook`, shared by the TCP
+//! server and the command line tools.
+//!
+//! Requests are one per line, with case-insensitive keywords:
+//!
+//! ```text
+//! BUY <order id> <price> <quantity> [type] [DISPLAY=<quantity>] [POSTONLY|SLIDE] [OWNER=<id>]
+//! SELL <order id> <price> <quantity> [type] [options]
+//! CANCEL <order id>
+//! MODIFY <order id> <BUY|SELL> <price> <quantity>
+//! ```
+//!
+//! where the type is one of `GTC` (the default), `GFD`, `FAK`, `FOK`, `MKT`,
+//! `GTD:<expiry>`, `STOP:<stop price>` or `STOPLIMIT:<stop price>`.
+//!
+//! Every request is answered with one or more `Response` lines.
+
+use crate::{
+    matching::MatchingAlgorithm, LivreError, MatchInfo, ModifyOrder, Order, OrderState, OrderType,
+    Orderbook, PostOnly, Side,
+};
+use std::fmt::Display;
+
+#[derive(Debug, Clone)]
+pub enum Request {
+    Add(Order),
+    Cancel(u64),
+    Modify(ModifyOrder),
+}
+
+impl Request {
+    pub fn order_id(&self) -> u64 {
+        match self {
+            Request::Add(order) => order.order_id,
+            Request::Cancel(order_id) => *order_id,
+            Request::Modify(order) => order.order_id,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let mut fields = line.split_whitespace();
+        let keyword = fields.next().ok_or("empty request")?.to_ascii_uppercase();
+        let request = match keyword.as_str() {
+            "BUY" | "SELL" => {
+                let side = if keyword == "BUY" {
+                    Side::Bid
+                } else {
+                    Side::Ask
+                };
+                let order_id = parse_number(fields.next(), "order id")?;
+                let price = parse_number(fields.next(), "price")?;
+                let quantity = parse_number(fields.next(), "quantity")?;
+                let mut order_type = OrderType::GoodTillCancel;
+                let mut options = Vec::new();
+                for field in fields.by_ref() {
+                    match parse_order_type(field)? {
+                        Some(parsed) => order_type = parsed,
+                        None => options.push(field),
+                    }
+                }
+
+                let mut order = Order::new(order_type, order_id, side, price, quantity);
+                for option in options {
+                    let option = option.to_ascii_uppercase();
+                    order = match option.split_once('=') {
+                        Some(("DISPLAY", value)) => {
+                            order.with_display_quantity(parse_number(Some(value), "display")?)
+                        }
+                        Some(("OWNER", value)) => {
+                            order.with_owner(parse_number(Some(value), "owner")?)
+                        }
+                        None if option == "POSTONLY" => order.with_post_only(PostOnly::Reject),
+                        None if option == "SLIDE" => order.with_post_only(PostOnly::Slide),
+                        _ => return Err(format!("unknown order option {option}")),
+                    };
+                }
+                Request::Add(order)
+            }
+            "CANCEL" => Request::Cancel(parse_number(fields.next(), "order id")?),
+            "MODIFY" => {
+                let order_id = parse_number(fields.next(), "order id")?;
+                let side = match fields.next().map(str::to_ascii_uppercase).as_deref() {
+                    Some("BUY") => Side::Bid,
+                    Some("SELL") => Side::Ask,
+                    _ => return Err("expected BUY or SELL".to_owned()),
+                };
+                let price = parse_number(fields.next(), "price")?;
+                let quantity = parse_number(fields.next(), "quantity")?;
+                Request::Modify(ModifyOrder::new(order_id, side, price, quantity))
+            }
+            _ => return Err(format!("unknown request {keyword}")),
+        };
+        match fields.next() {
+            Some(field) => Err(format!("unexpected field {field}")),
+            None => Ok(request),
+        }
+    }
+}
+
+/// Parses an order type field, returning `None` if the field is not an order type.
+pub fn parse_order_type(field: &str) -> Result<Option<OrderType>, String> {
+    let field = field.to_ascii_uppercase();
+    let (name, argument) = match field.split_once(':') {
+        Some((name, argument)) => (name, Some(argument)),
+        None => (field.as_str(), None),
+    };
+    let order_type = match (name, argument) {
+        ("GTC", None) => OrderType::GoodTillCancel,
+        ("GFD", None) => OrderType::GoodForDay,
+        ("FAK", None) => OrderType::FillAndKill,
+        ("FOK", None) => OrderType::FillOrKill,
+        ("MKT", None) => OrderType::Market,
+        ("GTD", Some(expiry)) => OrderType::GoodTillDate {
+            expiry: parse_number(Some(expiry), "expiry")?,
+        },
+        ("STOP", Some(stop_price)) => OrderType::StopMarket {
+            stop_price: parse_number(Some(stop_price), "stop price")?,
+        },
+        ("STOPLIMIT", Some(stop_price)) => OrderType::StopLimit {
+            stop_price: parse_number(Some(stop_price), "stop price")?,
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(order_type))
+}
+
+fn parse_number(field: Option<&str>, name: &str) -> Result<u64, String> {
+    let field = field.ok_or_else(|| format!("missing {name}"))?;
+    field.parse().map_err(|_| format!("invalid {name} {field}"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// The order was accepted, with its state once it finished matching.
+    Accepted {
+        order_id: u64,
+        state: OrderState,
+    },
+    Fill {
+        order_id: u64,
+        counterparty_order_id: u64,
+        price: u64,
+        quantity: u64,
+    },
+    /// The order left the book with `quantity` unfilled.
+    Cancelled {
+        order_id: u64,
+        quantity: u64,
+    },
+    Modified {
+        order_id: u64,
+        state: OrderState,
+    },
+    Rejected {
+        order_id: u64,
+        reason: LivreError,
+    },
+}
+
+impl Response {
+    /// The order the response is about, used to route it to the order's owner.
+    pub fn order_id(&self) -> u64 {
+        match *self {
+            Response::Accepted { order_id, .. }
+            | Response::Fill { order_id, .. }
+            | Response::Cancelled { order_id, .. }
+            | Response::Modified { order_id, .. }
+            | Response::Rejected { order_id, .. } => order_id,
+        }
+    }
+}
+
+impl Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let state = |state: &OrderState| match state {
+            OrderState::Unfilled => "NEW".to_owned(),
+            OrderState::PartialFill(filled) => format!("PARTIAL {filled}"),
+            OrderState::Filled => "FILLED".to_owned(),
+        };
+        match self {
+            Response::Accepted {
+                order_id,
+                state: order_state,
+            } => {
+                write!(f, "ACK {order_id} {}", state(order_state))
+            }
+            Response::Fill {
+                order_id,
+                counterparty_order_id,
+                price,
+                quantity,
+            } => write!(
+                f,
+                "FILL {order_id} {counterparty_order_id} {price} {quantity}"
+            ),
+            Response::Cancelled { order_id, quantity } => {
+                write!(f, "CANCELLED {order_id} {quantity}")
+            }
+            Response::Modified {
+                order_id,
+                state: order_state,
+            } => {
+                write!(f, "MODIFIED {order_id} {}", state(order_state))
+            }
+            Response::Rejected { order_id, reason } => write!(f, "REJECT {order_id} {reason}"),
+        }
+    }
+}
+
+/// Applies a request to the book, returning the responses for every order it
+/// affected: fills are reported to both the taker and the maker.
+pub fn apply<M: MatchingAlgorithm>(book: &mut Orderbook<M>, request: Request) -> Vec<Response> {
+    let order_id = request.order_id();
+    let result = match request {
+        Request::Add(order) => book.add_order(order).map(|match_info| {
+            (
+                Response::Accepted {
+                    order_id,
+                    state: match_info.order_state,
+                },
+                Some(match_info),
+            )
+        }),
+        Request::Cancel(order_id) => book.cancel_order(order_id).map(|order| {
+            (
+                Response::Cancelled {
+                    order_id,
+                    quantity: order.remaining_quantity,
+                },
+                None,
+            )
+        }),
+        Request::Modify(order) => book.modify_order(order).map(|match_info| {
+            (
+                Response::Modified {
+                    order_id,
+                    state: match_info.order_state,
+                },
+                Some(match_info),
+            )
+        }),
+    };
+
+    match result {
+        Ok((response, match_info)) => {
+            let mut responses = vec![response];
+            if let Some(match_info) = match_info {
+                responses.extend(match_responses(order_id, &match_info));
+            }
+            responses
+        }
+        Err(reason) => vec![Response::Rejected { order_id, reason }],
+    }
+}
+
+fn match_responses(order_id: u64, match_info: &MatchInfo) -> Vec<Response> {
+    let mut responses = Vec::new();
+    for trade in match_info
+        .trade_log
+        .iter()
+        .chain(&match_info.triggered_trade_log)
+    {
+        for (order_id, counterparty_order_id) in [
+            (trade.taker_order_id, trade.maker_order_id),
+            (trade.maker_order_id, trade.taker_order_id),
+        ] {
+            responses.push(Response::Fill {
+                order_id,
+                counterparty_order_id,
+                price: trade.price,
+                quantity: trade.quantity,
+            });
+        }
+    }
+    if match_info.cancelled_quantity > 0 {
+        responses.push(Response::Cancelled {
+            order_id,
+            quantity: match_info.cancelled_quantity,
+        });
+    }
+    responses
+}
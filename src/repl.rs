@@ -0,0 +1,283 @@
+//! An interactive shell over an `Orderbook` for debugging and demos.
+//!
+//! Order commands use the request syntax from `protocol`, so `buy 1 100 10 fak`
+//! places a fill-and-kill bid for 10 at 100. The shell also understands:
+//!
+//! ```text
+//! book [levels]   print a depth ladder, 10 levels a side by default
+//! trades          print every trade since the shell started
+//! expire          expire good-till-date and good-for-day orders that are due
+//! help            list the commands
+//! quit            leave the shell
+//! ```
+//!
+//! Blank lines and lines starting with `#` are ignored, so scenarios can be kept
+//! in commented script files.
+
+use crate::{
+    matching::{MatchingAlgorithm, PriceTimePriority},
+    protocol::Request,
+    DepthSnapshot, MatchInfo, OrderState, Orderbook, TradeLog,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+const DEFAULT_LEVELS: usize = 10;
+
+const HELP: &str = "\
+buy|sell <order id> <price> <quantity> [GTC|GFD|FAK|FOK|MKT|GTD:<expiry>|STOP:<price>|STOPLIMIT:<price>]
+         [DISPLAY=<quantity>] [POSTONLY|SLIDE] [OWNER=<id>]
+cancel <order id>
+modify <order id> <buy|sell> <price> <quantity>
+book [levels]
+trades
+expire
+quit";
+
+pub struct Repl<M = PriceTimePriority> {
+    book: Orderbook<M>,
+    trade_log: TradeLog,
+}
+
+impl<M: MatchingAlgorithm> Repl<M> {
+    pub fn new(book: Orderbook<M>) -> Self {
+        Self {
+            book,
+            trade_log: Vec::new(),
+        }
+    }
+
+    pub fn book(&self) -> &Orderbook<M> {
+        &self.book
+    }
+
+    pub fn trade_log(&self) -> &TradeLog {
+        &self.trade_log
+    }
+
+    /// Reads commands from `input` until it is exhausted or `quit` is entered.
+    ///
+    /// Interactive sessions print a prompt before every command, scripted ones echo
+    /// each command so the output reads as a transcript.
+    pub fn run(
+        &mut self,
+        input: impl BufRead,
+        mut output: impl Write,
+        interactive: bool,
+    ) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            if interactive {
+                write!(output, "livre> ")?;
+                output.flush()?;
+            }
+            let Some(line) = lines.next().transpose()? else {
+                if interactive {
+                    writeln!(output)?;
+                }
+                break;
+            };
+            let command = line.trim();
+            if command.is_empty() || command.starts_with('#') {
+                continue;
+            }
+            if !interactive {
+                writeln!(output, "> {command}")?;
+            }
+            if command.eq_ignore_ascii_case("quit") || command.eq_ignore_ascii_case("exit") {
+                break;
+            }
+            write!(output, "{}", self.execute(command))?;
+        }
+        Ok(())
+    }
+
+    /// Runs a single command, returning what it printed.
+    pub fn execute(&mut self, command: &str) -> String {
+        let mut fields = command.split_whitespace();
+        let keyword = fields.next().unwrap_or_default().to_ascii_lowercase();
+        match keyword.as_str() {
+            "book" => match fields.next().map(str::parse) {
+                None => self.ladder(DEFAULT_LEVELS),
+                Some(Ok(levels)) => self.ladder(levels),
+                Some(Err(_)) => "error: invalid level count\n".to_owned(),
+            },
+            "trades" => self.trades(),
+            "expire" => {
+                let mut out = String::new();
+                for expired in self.book.expire_orders() {
+                    let order = &expired.order;
+                    let _ = writeln!(
+                        out,
+                        "order {} expired with {} unfilled",
+                        order.order_id(),
+                        order.remaining_quantity()
+                    );
+                }
+                if out.is_empty() {
+                    out.push_str("no orders expired\n");
+                }
+                out
+            }
+            "help" => format!("{HELP}\n"),
+            _ => match Request::parse(command) {
+                Ok(request) => self.submit(request),
+                Err(err) => format!("error: {err}\n"),
+            },
+        }
+    }
+
+    fn submit(&mut self, request: Request) -> String {
+        let order_id = request.order_id();
+        let result = match request {
+            Request::Add(order) => self.book.add_order(order),
+            Request::Modify(order) => self.book.modify_order(order),
+            Request::Cancel(order_id) => {
+                return match self.book.cancel_order(order_id) {
+                    Ok(order) => format!(
+                        "order {order_id} cancelled with {} unfilled\n",
+                        order.remaining_quantity()
+                    ),
+                    Err(err) => format!("order {order_id} rejected: {err}\n"),
+                };
+            }
+        };
+        match result {
+            Ok(match_info) => self.report(order_id, match_info),
+            Err(err) => format!("order {order_id} rejected: {err}\n"),
+        }
+    }
+
+    fn report(&mut self, order_id: u64, match_info: MatchInfo) -> String {
+        let mut out = match match_info.order_state {
+            OrderState::Unfilled => format!("order {order_id} accepted"),
+            OrderState::PartialFill(filled) => format!("order {order_id} filled {filled}"),
+            OrderState::Filled => format!("order {order_id} filled"),
+        };
+        if match_info.cancelled_quantity > 0 {
+            let _ = write!(out, ", {} cancelled", match_info.cancelled_quantity);
+        }
+        out.push('\n');
+
+        let first = self.trade_log.len();
+        self.trade_log.extend(match_info.trade_log);
+        self.trade_log.extend(match_info.triggered_trade_log);
+        for (number, trade) in self.trade_log.iter().enumerate().skip(first) {
+            let _ = writeln!(
+                out,
+                "  trade #{}: {} @ {} (taker {}, maker {})",
+                number + 1,
+                trade.quantity,
+                trade.price,
+                trade.taker_order_id,
+                trade.maker_order_id
+            );
+        }
+        for triggered in match_info.triggered_orders {
+            let _ = writeln!(out, "  stop order {triggered} triggered");
+        }
+        out
+    }
+
+    fn trades(&self) -> String {
+        if self.trade_log.is_empty() {
+            return "no trades\n".to_owned();
+        }
+        let mut out = format!(
+            "{:>5} {:>10} {:>10} {:>10} {:>10}\n",
+            "#", "price", "quantity", "taker", "maker"
+        );
+        for (number, trade) in self.trade_log.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "{:>5} {:>10} {:>10} {:>10} {:>10}",
+                number + 1,
+                trade.price,
+                trade.quantity,
+                trade.taker_order_id,
+                trade.maker_order_id
+            );
+        }
+        out
+    }
+
+    /// Prints asks above bids with the best prices meeting in the middle.
+    fn ladder(&self, levels: usize) -> String {
+        let DepthSnapshot { bids, asks } = self.book.depth(levels);
+        let mut out = format!("{:>14} | {:>10} | {}\n", "bids", "price", "asks");
+        for level in asks.iter().rev() {
+            let asks = format!("{} ({})", level.quantity, level.order_count);
+            let _ = writeln!(out, "{:>14} | {:>10} | {asks}", "", level.price);
+        }
+        if let Some(spread) = self.book.spread() {
+            let _ = writeln!(out, "{:>14} | {:>10} |", "", format!("-{spread}-"));
+        } else {
+            let _ = writeln!(out, "{:>14} | {:>10} |", "", "----");
+        }
+        for level in &bids {
+            let bids = format!("{} ({})", level.quantity, level.order_count);
+            let _ = writeln!(out, "{bids:>14} | {:>10} |", level.price);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_commands_report_fills_and_trades() {
+        let mut repl = Repl::new(Orderbook::new());
+        assert_eq!(repl.execute("sell 1 100 10"), "order 1 accepted\n");
+        assert_eq!(
+            repl.execute("buy 2 100 4"),
+            "order 2 filled\n  trade #1: 4 @ 100 (taker 2, maker 1)\n"
+        );
+        assert_eq!(
+            repl.execute("buy 3 100 10 fak"),
+            "order 3 filled 6, 4 cancelled\n  trade #2: 6 @ 100 (taker 3, maker 1)\n"
+        );
+        assert_eq!(repl.trade_log().len(), 2);
+        assert_eq!(
+            repl.execute("cancel 1"),
+            "order 1 rejected: could not find order matching id\n"
+        );
+        assert!(repl.execute("buy x").starts_with("error: "));
+        assert_eq!(repl.execute("book x"), "error: invalid level count\n");
+    }
+
+    #[test]
+    fn book_prints_asks_above_bids() {
+        let mut repl = Repl::new(Orderbook::new());
+        repl.execute("sell 1 102 5");
+        repl.execute("sell 2 102 5");
+        repl.execute("buy 3 100 7");
+        let ladder = repl.execute("book 1");
+        let lines: Vec<_> = ladder.lines().map(str::trim).collect();
+        assert_eq!(
+            lines,
+            [
+                "bids |      price | asks",
+                "|        102 | 10 (2)",
+                "|        -2- |",
+                "7 (1) |        100 |",
+            ]
+        );
+    }
+
+    #[test]
+    fn scripts_echo_commands_and_stop_at_quit() {
+        let script = "# a comment\n\nsell 1 100 10\nquit\nbuy 2 100 10\n";
+        let mut output = Vec::new();
+        let mut repl = Repl::new(Orderbook::new());
+        repl.run(script.as_bytes(), &mut output, false).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "> sell 1 100 10\norder 1 accepted\n> quit\n"
+        );
+        assert_eq!(repl.book().order_count(), 1);
+    }
+}
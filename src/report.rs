@@ -0,0 +1,124 @@
+use crate::{clock::Timestamp, LivreError, Side};
+
+/// Whether an order removed liquidity from the book or provided the liquidity that
+/// was removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Taker,
+    Maker,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    /// Cancelled through `Orderbook::cancel_order`.
+    Requested,
+    /// The rest of an order whose type does not let it rest in the book, such as
+    /// a fill and kill or market order.
+    Unfilled,
+    SelfTradePrevention,
+    /// The order tried to trade outside the book's price band.
+    CircuitBreaker,
+}
+
+/// A change in the lifecycle of a single order. Every mutation of an `Orderbook`
+/// produces the reports of the incoming order along with those of every resting
+/// order it touched, in the order they happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionReport {
+    /// The order passed validation. Stop orders are accepted into the trigger book
+    /// and report nothing further until they are triggered.
+    Accepted {
+        order_id: u64,
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+    /// The order was replaced through `Orderbook::modify_order`, losing its priority.
+    Replaced {
+        order_id: u64,
+        side: Side,
+        price: u64,
+        quantity: u64,
+    },
+    /// A stop order's stop price was reached and it was released for matching.
+    Triggered {
+        order_id: u64,
+    },
+    /// The order joined its price level after matching what it could.
+    Rested {
+        order_id: u64,
+        side: Side,
+        price: u64,
+        remaining_quantity: u64,
+    },
+    PartiallyFilled {
+        order_id: u64,
+        liquidity: Liquidity,
+        price: u64,
+        quantity: u64,
+        remaining_quantity: u64,
+    },
+    Filled {
+        order_id: u64,
+        liquidity: Liquidity,
+        price: u64,
+        quantity: u64,
+    },
+    /// The order left the book with `quantity` unfilled.
+    Cancelled {
+        order_id: u64,
+        quantity: u64,
+        reason: CancelReason,
+    },
+    Expired {
+        order_id: u64,
+        quantity: u64,
+        expired_at: Timestamp,
+    },
+    Rejected {
+        order_id: u64,
+        reason: LivreError,
+    },
+}
+
+impl ExecutionReport {
+    pub fn order_id(&self) -> u64 {
+        match *self {
+            ExecutionReport::Accepted { order_id, .. }
+            | ExecutionReport::Replaced { order_id, .. }
+            | ExecutionReport::Triggered { order_id }
+            | ExecutionReport::Rested { order_id, .. }
+            | ExecutionReport::PartiallyFilled { order_id, .. }
+            | ExecutionReport::Filled { order_id, .. }
+            | ExecutionReport::Cancelled { order_id, .. }
+            | ExecutionReport::Expired { order_id, .. }
+            | ExecutionReport::Rejected { order_id, .. } => order_id,
+        }
+    }
+
+    /// The report of an order having traded `quantity` at `price`.
+    pub(crate) fn fill(
+        order_id: u64,
+        liquidity: Liquidity,
+        price: u64,
+        quantity: u64,
+        remaining_quantity: u64,
+    ) -> Self {
+        if remaining_quantity == 0 {
+            ExecutionReport::Filled {
+                order_id,
+                liquidity,
+                price,
+                quantity,
+            }
+        } else {
+            ExecutionReport::PartiallyFilled {
+                order_id,
+                liquidity,
+                price,
+                quantity,
+                remaining_quantity,
+            }
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use crate::{
+    auction::UncrossResult,
+    matching::{MatchingAlgorithm, PriceTimePriority},
+    phase::{PhaseChange, TradingPhase},
+    report::ExecutionReport,
+    ExpiredOrder, LivreError, MatchInfo, ModifyOrder, Order, OrderType, Orderbook, Side,
+};
+use std::{collections::HashMap, error::Error, fmt::Display};
+
+/// Why the risk layer refused an order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskReject {
+    QuantityAboveLimit {
+        quantity: u64,
+        limit: u64,
+    },
+    NotionalAboveLimit {
+        notional: u128,
+        limit: u128,
+    },
+    PriceOutsideCollar {
+        price: u64,
+        reference_price: u64,
+        collar_bps: u64,
+    },
+    TooManyOpenOrders {
+        account: u64,
+        limit: usize,
+    },
+    CreditLimitExceeded {
+        account: u64,
+        required: u128,
+        available: u128,
+    },
+    /// The order passed the risk checks but was rejected by the book.
+    Book(LivreError),
+}
+
+impl Display for RiskReject {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RiskReject::QuantityAboveLimit { quantity, limit } => {
+                write!(f, "quantity {quantity} is above the limit of {limit}")
+            }
+            RiskReject::NotionalAboveLimit { notional, limit } => {
+                write!(f, "notional {notional} is above the limit of {limit}")
+            }
+            RiskReject::PriceOutsideCollar {
+                price,
+                reference_price,
+                collar_bps,
+            } => write!(
+                f,
+                "price {price} is more than {collar_bps} bps away from {reference_price}"
+            ),
+            RiskReject::TooManyOpenOrders { account, limit } => {
+                write!(f, "account {account} already has {limit} open orders")
+            }
+            RiskReject::CreditLimitExceeded {
+                account,
+                required,
+                available,
+            } => write!(
+                f,
+                "account {account} needs {required} credit but has {available} available"
+            ),
+            RiskReject::Book(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for RiskReject {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RiskReject::Book(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<LivreError> for RiskReject {
+    fn from(err: LivreError) -> Self {
+        RiskReject::Book(err)
+    }
+}
+
+/// Limits applied to every order. `None` disables a check.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RiskLimits {
+    pub max_quantity: Option<u64>,
+    pub max_notional: Option<u128>,
+    /// How far a limit price may be from the best opposite price, or the best
+    /// price on its own side when the opposite side is empty, in basis points.
+    pub price_collar_bps: Option<u64>,
+}
+
+/// Limits applied to the orders of one account, identified by the order's owner.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AccountLimits {
+    pub max_open_orders: Option<usize>,
+    /// The total notional the account's open orders may have.
+    pub credit_limit: Option<u128>,
+}
+
+/// What an account's open orders currently hold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AccountUsage {
+    pub open_orders: usize,
+    pub credit_used: u128,
+}
+
+/// The credit held by an order resting in the book or waiting in the trigger book.
+struct OpenOrder {
+    account: u64,
+    notional: u128,
+}
+
+/// An `Orderbook` that runs pre-trade risk checks before every order and
+/// modification, and tracks the open orders and credit each account holds.
+///
+/// Orders without an owner are only subject to the book wide `RiskLimits`. Credit
+/// is consumed at the order's price for its remaining quantity, and released as
+/// the order fills, is cancelled or expires.
+pub struct RiskCheckedOrderbook<M = PriceTimePriority> {
+    book: Orderbook<M>,
+    limits: RiskLimits,
+    accounts: HashMap<u64, AccountLimits>,
+    usage: HashMap<u64, AccountUsage>,
+    open_orders: HashMap<u64, OpenOrder>,
+}
+
+impl<M: MatchingAlgorithm> RiskCheckedOrderbook<M> {
+    /// Wraps a book, which should not have any orders yet since orders already in
+    /// it are not tracked.
+    pub fn new(book: Orderbook<M>, limits: RiskLimits) -> Self {
+        Self {
+            book,
+            limits,
+            accounts: HashMap::new(),
+            usage: HashMap::new(),
+            open_orders: HashMap::new(),
+        }
+    }
+
+    pub fn set_limits(&mut self, limits: RiskLimits) {
+        self.limits = limits;
+    }
+
+    pub fn set_account_limits(&mut self, account: u64, limits: AccountLimits) {
+        self.accounts.insert(account, limits);
+    }
+
+    pub fn account_usage(&self, account: u64) -> AccountUsage {
+        self.usage.get(&account).copied().unwrap_or_default()
+    }
+
+    pub fn add_order(&mut self, order: Order) -> Result<MatchInfo, RiskReject> {
+        let notional = self.check_order(&order)?;
+        if let Some(account) = order.owner {
+            let limits = self.accounts.get(&account).copied().unwrap_or_default();
+            let usage = self.account_usage(account);
+            if let Some(limit) = limits.max_open_orders {
+                if usage.open_orders >= limit {
+                    return Err(RiskReject::TooManyOpenOrders { account, limit });
+                }
+            }
+            Self::check_credit(account, limits, usage.credit_used, notional)?;
+        }
+
+        let order_id = order.order_id;
+        let match_info = self.book.add_order(order)?;
+        self.refresh(order_id);
+        self.refresh_reported(&match_info.reports);
+        Ok(match_info)
+    }
+
+    pub fn cancel_order(&mut self, order_id: u64) -> Result<Order, RiskReject> {
+        let order = self.book.cancel_order(order_id)?;
+        self.refresh(order_id);
+        Ok(order)
+    }
+
+    /// Checks the replacement like a new order, except that the credit held by the
+    /// order being replaced counts as available.
+    pub fn modify_order(&mut self, order: ModifyOrder) -> Result<MatchInfo, RiskReject> {
+        let order_id = order.order_id;
+        let old_order = self
+            .book
+            .find_order(order_id)
+            .ok_or(LivreError::OrderNotFound)?
+            .clone();
+        let notional = self.check_order(&order.to_order(&old_order))?;
+        if let Some(account) = old_order.owner {
+            let limits = self.accounts.get(&account).copied().unwrap_or_default();
+            let held = self
+                .open_orders
+                .get(&order_id)
+                .map_or(0, |open_order| open_order.notional);
+            let credit_used = self.account_usage(account).credit_used - held;
+            Self::check_credit(account, limits, credit_used, notional)?;
+        }
+
+        let match_info = self.book.modify_order(order)?;
+        self.refresh(order_id);
+        self.refresh_reported(&match_info.reports);
+        Ok(match_info)
+    }
+
+    pub fn expire_orders(&mut self) -> Vec<ExpiredOrder> {
+        let expired = self.book.expire_orders();
+        for expired_order in &expired {
+            self.refresh(expired_order.order.order_id);
+        }
+        expired
+    }
+
+    pub fn set_phase(&mut self, phase: TradingPhase) -> Result<Option<UncrossResult>, LivreError> {
+        let uncross = self.book.set_phase(phase)?;
+        if let Some(uncross) = &uncross {
+            self.refresh_reported(&uncross.reports);
+        }
+        Ok(uncross)
+    }
+
+    pub fn update_phase(&mut self) -> Vec<PhaseChange> {
+        let changes = self.book.update_phase();
+        for uncross in changes.iter().filter_map(|change| change.uncross.as_ref()) {
+            self.refresh_reported(&uncross.reports);
+        }
+        changes
+    }
+
+    pub fn book(&self) -> &Orderbook<M> {
+        &self.book
+    }
+
+    pub fn into_inner(self) -> Orderbook<M> {
+        self.book
+    }
+
+    /// Runs the book wide checks, returning the order's notional.
+    fn check_order(&self, order: &Order) -> Result<u128, RiskReject> {
+        let quantity = order.remaining_quantity;
+        if let Some(limit) = self.limits.max_quantity {
+            if quantity > limit {
+                return Err(RiskReject::QuantityAboveLimit { quantity, limit });
+            }
+        }
+
+        let notional = self.notional(order);
+        if let Some(limit) = self.limits.max_notional {
+            if notional > limit {
+                return Err(RiskReject::NotionalAboveLimit { notional, limit });
+            }
+        }
+
+        // market and stop market orders have no limit price to check
+        let limit_priced = !matches!(
+            order.order_type,
+            OrderType::Market | OrderType::StopMarket { .. }
+        );
+        if let (Some(collar_bps), true) = (self.limits.price_collar_bps, limit_priced) {
+            let (opposite, same) = match order.side {
+                Side::Bid => (self.book.best_ask(), self.book.best_bid()),
+                Side::Ask => (self.book.best_bid(), self.book.best_ask()),
+            };
+            if let Some(reference_price) = opposite.or(same) {
+                let width = (reference_price as u128 * collar_bps as u128 / 10_000) as u64;
+                if order.price.abs_diff(reference_price) > width {
+                    return Err(RiskReject::PriceOutsideCollar {
+                        price: order.price,
+                        reference_price,
+                        collar_bps,
+                    });
+                }
+            }
+        }
+        Ok(notional)
+    }
+
+    fn check_credit(
+        account: u64,
+        limits: AccountLimits,
+        credit_used: u128,
+        required: u128,
+    ) -> Result<(), RiskReject> {
+        match limits.credit_limit {
+            Some(limit) if credit_used + required > limit => Err(RiskReject::CreditLimitExceeded {
+                account,
+                required,
+                available: limit.saturating_sub(credit_used),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Values the remaining quantity at the order's price. Stop market orders are
+    /// valued at their stop price, and market orders at the prices of the levels
+    /// they would sweep, as far as the book's market protection lets them go.
+    fn notional(&self, order: &Order) -> u128 {
+        let price = match order.order_type {
+            OrderType::StopMarket { stop_price } => stop_price,
+            OrderType::Market => return self.sweep_notional(order.side, order.remaining_quantity),
+            _ => order.price,
+        };
+        price as u128 * order.remaining_quantity as u128
+    }
+
+    fn sweep_notional(&self, side: Side, mut quantity: u64) -> u128 {
+        let Some(limit_price) = self.book.market_limit_price(side) else {
+            return 0;
+        };
+        let levels: Box<dyn Iterator<Item = _>> = match side {
+            Side::Bid => Box::new(
+                self.book
+                    .asks
+                    .iter()
+                    .take_while(move |(&price, _)| price <= limit_price),
+            ),
+            Side::Ask => Box::new(
+                self.book
+                    .bids
+                    .iter()
+                    .rev()
+                    .take_while(move |(&price, _)| price >= limit_price),
+            ),
+        };
+        let mut notional = 0;
+        for (&price, queue) in levels {
+            // hidden reserves trade as well, so count the full remaining quantity
+            let level_quantity = queue.iter().map(Order::remaining_quantity).sum::<u64>();
+            let filled = quantity.min(level_quantity);
+            notional += price as u128 * filled as u128;
+            quantity -= filled;
+            if quantity == 0 {
+                break;
+            }
+        }
+        notional
+    }
+
+    /// Brings the credit held by an order in line with what is left of it in the
+    /// book.
+    fn refresh(&mut self, order_id: u64) {
+        if let Some(open_order) = self.open_orders.remove(&order_id) {
+            let usage = self.usage.entry(open_order.account).or_default();
+            usage.open_orders -= 1;
+            usage.credit_used -= open_order.notional;
+        }
+
+        let Some(order) = self.book.find_order(order_id) else {
+            return;
+        };
+        let Some(account) = order.owner else {
+            return;
+        };
+        let notional = self.notional(order);
+        let usage = self.usage.entry(account).or_default();
+        usage.open_orders += 1;
+        usage.credit_used += notional;
+        self.open_orders
+            .insert(order_id, OpenOrder { account, notional });
+    }
+
+    /// Refreshes every order a mutation touched, which all have a lifecycle report.
+    fn refresh_reported(&mut self, reports: &[ExecutionReport]) {
+        for report in reports {
+            self.refresh(report.order_id());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn risk_book() -> RiskCheckedOrderbook {
+        let mut book = RiskCheckedOrderbook::new(
+            Orderbook::new(),
+            RiskLimits {
+                max_quantity: Some(100),
+                max_notional: Some(50_000),
+                price_collar_bps: Some(1_000),
+            },
+        );
+        book.set_account_limits(
+            7,
+            AccountLimits {
+                max_open_orders: Some(2),
+                credit_limit: Some(3_000),
+            },
+        );
+        book
+    }
+
+    fn ask(order_id: u64, price: u64, quantity: u64) -> Order {
+        Order::new(
+            OrderType::GoodTillCancel,
+            order_id,
+            Side::Ask,
+            price,
+            quantity,
+        )
+        .with_owner(7)
+    }
+
+    #[test]
+    fn book_wide_limits() {
+        let mut book = risk_book();
+        book.add_order(ask(1, 100, 10)).unwrap();
+        let order =
+            |price, quantity| Order::new(OrderType::GoodTillCancel, 2, Side::Bid, price, quantity);
+        assert_eq!(
+            book.add_order(order(100, 101)).err(),
+            Some(RiskReject::QuantityAboveLimit {
+                quantity: 101,
+                limit: 100
+            })
+        );
+        assert_eq!(
+            book.add_order(order(600, 100)).err(),
+            Some(RiskReject::NotionalAboveLimit {
+                notional: 60_000,
+                limit: 50_000
+            })
+        );
+        assert_eq!(
+            book.add_order(order(89, 1)).err(),
+            Some(RiskReject::PriceOutsideCollar {
+                price: 89,
+                reference_price: 100,
+                collar_bps: 1_000
+            })
+        );
+        book.add_order(order(90, 1)).unwrap();
+        assert_eq!(book.book().order_count(), 2);
+    }
+
+    #[test]
+    fn accounts_are_limited_in_open_orders_and_credit() {
+        let mut book = risk_book();
+        book.add_order(ask(1, 100, 10)).unwrap();
+        book.add_order(ask(2, 101, 10)).unwrap();
+        assert_eq!(
+            book.account_usage(7),
+            AccountUsage {
+                open_orders: 2,
+                credit_used: 2_010
+            }
+        );
+        assert_eq!(
+            book.add_order(ask(3, 100, 1)).err(),
+            Some(RiskReject::TooManyOpenOrders {
+                account: 7,
+                limit: 2
+            })
+        );
+
+        book.cancel_order(2).unwrap();
+        assert_eq!(
+            book.add_order(ask(3, 100, 25)).err(),
+            Some(RiskReject::CreditLimitExceeded {
+                account: 7,
+                required: 2_500,
+                available: 2_000
+            })
+        );
+        assert_eq!(book.account_usage(7).open_orders, 1);
+    }
+
+    #[test]
+    fn credit_follows_fills_and_modifications() {
+        let mut book = risk_book();
+        book.add_order(ask(1, 100, 10)).unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 4))
+            .unwrap();
+        assert_eq!(book.account_usage(7).credit_used, 600);
+
+        // the credit held by the order being replaced counts as available
+        assert!(matches!(
+            book.modify_order(ModifyOrder::new(1, Side::Ask, 100, 31)),
+            Err(RiskReject::CreditLimitExceeded {
+                required: 3_100,
+                ..
+            })
+        ));
+        assert_eq!(book.account_usage(7).credit_used, 600);
+        book.modify_order(ModifyOrder::new(1, Side::Ask, 100, 30))
+            .unwrap();
+        assert_eq!(book.account_usage(7).credit_used, 3_000);
+
+        book.add_order(Order::new(OrderType::FillAndKill, 3, Side::Bid, 100, 30))
+            .unwrap();
+        assert_eq!(book.account_usage(7), AccountUsage::default());
+    }
+
+    #[test]
+    fn market_orders_are_valued_at_the_levels_they_sweep() {
+        let mut book = RiskCheckedOrderbook::new(Orderbook::new(), RiskLimits::default());
+        for (order_id, price) in [(1, 10), (2, 1_000)] {
+            book.add_order(Order::new(
+                OrderType::GoodTillCancel,
+                order_id,
+                Side::Ask,
+                price,
+                10,
+            ))
+            .unwrap();
+        }
+        book.set_limits(RiskLimits {
+            max_notional: Some(1_000),
+            ..RiskLimits::default()
+        });
+        let market =
+            |order_id, quantity| Order::new(OrderType::Market, order_id, Side::Bid, 0, quantity);
+        assert_eq!(
+            book.add_order(market(3, 20)).err(),
+            Some(RiskReject::NotionalAboveLimit {
+                notional: 10_100,
+                limit: 1_000
+            })
+        );
+        book.add_order(market(3, 10)).unwrap();
+        assert_eq!(book.book().best_ask(), Some(1_000));
+    }
+}
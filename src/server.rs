@@ -0,0 +1,223 @@
+//! A TCP order entry server speaking the line protocol from `protocol`.
+//!
+//! Every connection is read on its own thread, but all requests are funnelled into
+//! a single matching thread that owns the `Orderbook`, so they are applied one at a
+//! time in arrival order. Responses are written back to the connection that owns
+//! the order they concern, so a resting order's fills reach the client that placed
+//! it even when another client's order took it.
+
+use crate::{
+    matching::MatchingAlgorithm,
+    protocol::{self, Request, Response},
+    LivreError, Orderbook,
+};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+type ConnectionId = u64;
+
+enum Event {
+    Connected(ConnectionId, Sender<String>),
+    Line(ConnectionId, String),
+    Disconnected(ConnectionId),
+}
+
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections until the listener fails, applying their requests to
+    /// `book`.
+    pub fn run<M: MatchingAlgorithm + Send + 'static>(self, book: Orderbook<M>) -> io::Result<()> {
+        let (events, receiver) = mpsc::channel();
+        thread::spawn(move || match_requests(book, receiver));
+
+        for (connection_id, stream) in (1..).zip(self.listener.incoming()) {
+            let stream = stream?;
+            let writer = stream.try_clone()?;
+            let (responses, outbox) = mpsc::channel();
+            if events
+                .send(Event::Connected(connection_id, responses))
+                .is_err()
+            {
+                break;
+            }
+            thread::spawn(move || write_responses(writer, outbox));
+            let events = events.clone();
+            thread::spawn(move || read_requests(connection_id, stream, events));
+        }
+        Ok(())
+    }
+}
+
+fn read_requests(connection_id: ConnectionId, stream: TcpStream, events: Sender<Event>) {
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if events.send(Event::Line(connection_id, line)).is_err() {
+            return;
+        }
+    }
+    let _ = events.send(Event::Disconnected(connection_id));
+}
+
+fn write_responses(mut stream: TcpStream, outbox: Receiver<String>) {
+    for mut line in outbox {
+        line.push('\n');
+        if stream.write_all(line.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn match_requests<M: MatchingAlgorithm>(mut book: Orderbook<M>, events: Receiver<Event>) {
+    let mut connections: HashMap<ConnectionId, Sender<String>> = HashMap::new();
+    let mut order_owners: HashMap<u64, ConnectionId> = HashMap::new();
+
+    for event in events {
+        match event {
+            Event::Connected(connection_id, responses) => {
+                connections.insert(connection_id, responses);
+            }
+            Event::Disconnected(connection_id) => {
+                connections.remove(&connection_id);
+            }
+            Event::Line(connection_id, line) => {
+                let request = match Request::parse(&line) {
+                    Ok(request) => request,
+                    Err(err) => {
+                        if let Some(responses) = connections.get(&connection_id) {
+                            let _ = responses.send(format!("ERROR {err}"));
+                        }
+                        continue;
+                    }
+                };
+                let is_add = matches!(request, Request::Add(_));
+                let order_id = request.order_id();
+                let owned_elsewhere = order_owners
+                    .get(&order_id)
+                    .is_some_and(|&owner| owner != connection_id);
+                // clients may only cancel or modify their own orders
+                let responses = if !is_add && owned_elsewhere {
+                    vec![Response::Rejected {
+                        order_id,
+                        reason: LivreError::OrderNotFound,
+                    }]
+                } else {
+                    protocol::apply(&mut book, request)
+                };
+                if is_add && !matches!(responses[0], Response::Rejected { .. }) {
+                    order_owners.insert(order_id, connection_id);
+                }
+
+                for response in responses {
+                    // rejected requests may name an order owned by someone else
+                    let owner = match response {
+                        Response::Rejected { .. } => connection_id,
+                        _ => order_owners
+                            .get(&response.order_id())
+                            .copied()
+                            .unwrap_or(connection_id),
+                    };
+                    if let Some(outbox) = connections.get(&owner) {
+                        let _ = outbox.send(response.to_string());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Self {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            Self {
+                writer: stream.try_clone().unwrap(),
+                reader: BufReader::new(stream),
+            }
+        }
+
+        fn send(&mut self, line: &str) {
+            writeln!(self.writer, "{line}").unwrap();
+        }
+
+        fn receive(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line.trim_end().to_owned()
+        }
+    }
+
+    fn start_server() -> SocketAddr {
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run(Orderbook::new()));
+        addr
+    }
+
+    #[test]
+    fn fills_reach_the_owner_of_the_resting_order() {
+        let addr = start_server();
+        let mut maker = Client::connect(addr);
+        let mut taker = Client::connect(addr);
+
+        maker.send("SELL 1 100 10");
+        assert_eq!(maker.receive(), "ACK 1 NEW");
+        taker.send("BUY 2 100 4");
+        assert_eq!(taker.receive(), "ACK 2 FILLED");
+        assert_eq!(taker.receive(), "FILL 2 1 100 4");
+        assert_eq!(maker.receive(), "FILL 1 2 100 4");
+    }
+
+    #[test]
+    fn clients_cannot_cancel_orders_of_other_clients() {
+        let addr = start_server();
+        let mut owner = Client::connect(addr);
+        let mut other = Client::connect(addr);
+
+        owner.send("BUY 1 100 10");
+        assert_eq!(owner.receive(), "ACK 1 NEW");
+        other.send("CANCEL 1");
+        assert_eq!(
+            other.receive(),
+            format!("REJECT 1 {}", LivreError::OrderNotFound)
+        );
+        other.send("BOGUS");
+        assert!(other.receive().starts_with("ERROR"));
+        owner.send("CANCEL 1");
+        assert_eq!(owner.receive(), "CANCELLED 1 10");
+    }
+}
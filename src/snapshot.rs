@@ -0,0 +1,485 @@
+use crate::{
+    instrument::InstrumentSpec,
+    matching::MatchingAlgorithm,
+    phase::{BandAction, PriceBand, TradingPhase},
+    LevelIdentifier, Order, OrderType, Orderbook, PostOnly, SelfTradePrevention, Side,
+};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{self, Read, Write},
+};
+
+const MAGIC: &[u8; 8] = b"LIVRSNAP";
+
+/// Version written by `write_snapshot`. Snapshots of any other version are
+/// rejected.
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// Writes the full state of `book` to `writer`: resting orders in queue order,
+/// stop orders, book settings and instrument reference data, the trading phase and
+/// its schedule, and sequence counters. `journal_sequence` is the sequence number
+/// of the last journaled command applied to the book, so the journal can be
+/// replayed from the following entry after a restore.
+///
+/// The matching algorithm, clock and market data subscription are not part of
+/// the snapshot.
+pub fn write_snapshot<M, W: Write>(
+    book: &Orderbook<M>,
+    journal_sequence: u64,
+    mut writer: W,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+
+    let writer = &mut writer;
+    write_u64(writer, journal_sequence)?;
+    write_u64(writer, book.market_data.last_sequence())?;
+    write_optional(writer, book.last_trade_price)?;
+    write_optional(writer, book.market_protection)?;
+    write_optional(writer, book.session_end)?;
+    write_u8(
+        writer,
+        match book.self_trade_prevention {
+            None => 0,
+            Some(SelfTradePrevention::CancelNewest) => 1,
+            Some(SelfTradePrevention::CancelOldest) => 2,
+            Some(SelfTradePrevention::CancelBoth) => 3,
+            Some(SelfTradePrevention::Decrement) => 4,
+        },
+    )?;
+    for book_side in [&book.bids, &book.asks, &book.bid_stops, &book.ask_stops] {
+        write_levels(writer, book_side)?;
+    }
+    write_u8(writer, book.auction as u8)?;
+    write_u8(writer, encode_phase(book.phase))?;
+    write_u64(writer, book.schedule.len() as u64)?;
+    for &(time, phase) in &book.schedule {
+        write_u64(writer, time)?;
+        write_u8(writer, encode_phase(phase))?;
+    }
+    write_optional(writer, book.price_band.map(|band| band.width_bps))?;
+    write_u8(
+        writer,
+        match book.price_band.map(|band| band.action) {
+            None | Some(BandAction::Halt) => 0,
+            Some(BandAction::VolatilityAuction) => 1,
+        },
+    )?;
+    let instrument = &book.instrument;
+    write_u64(writer, instrument.tick_table().len() as u64)?;
+    for &(from_price, tick_size) in instrument.tick_table() {
+        write_u64(writer, from_price)?;
+        write_u64(writer, tick_size)?;
+    }
+    write_u64(writer, instrument.lot_size())?;
+    write_optional(writer, instrument.min_quantity())?;
+    write_optional(writer, instrument.max_quantity())?;
+    writer.flush()
+}
+
+/// Replaces the state of `book` with the snapshot read from `reader`, returning the
+/// journal sequence number the snapshot was taken at.
+pub fn read_snapshot<M: MatchingAlgorithm, R: Read>(
+    book: &mut Orderbook<M>,
+    mut reader: R,
+) -> io::Result<u64> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid_data("not a livre snapshot"));
+    }
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    match u16::from_le_bytes(version) {
+        SNAPSHOT_VERSION => read_state(book, &mut reader),
+        version => Err(invalid_data(&format!(
+            "unsupported snapshot version {version}"
+        ))),
+    }
+}
+
+fn read_state<M: MatchingAlgorithm>(
+    book: &mut Orderbook<M>,
+    reader: &mut impl Read,
+) -> io::Result<u64> {
+    let journal_sequence = read_u64(reader)?;
+    let market_data_sequence = read_u64(reader)?;
+    let last_trade_price = read_optional(reader)?;
+    let market_protection = read_optional(reader)?;
+    let session_end = read_optional(reader)?;
+    let self_trade_prevention = match read_u8(reader)? {
+        0 => None,
+        1 => Some(SelfTradePrevention::CancelNewest),
+        2 => Some(SelfTradePrevention::CancelOldest),
+        3 => Some(SelfTradePrevention::CancelBoth),
+        4 => Some(SelfTradePrevention::Decrement),
+        _ => return Err(invalid_data("invalid self-trade prevention mode")),
+    };
+    let bids = read_levels(reader)?;
+    let asks = read_levels(reader)?;
+    let bid_stops = read_levels(reader)?;
+    let ask_stops = read_levels(reader)?;
+    let auction = match read_u8(reader)? {
+        0 => false,
+        1 => true,
+        _ => return Err(invalid_data("invalid auction flag")),
+    };
+    let phase = decode_phase(read_u8(reader)?)?;
+    let schedule = (0..read_u64(reader)?)
+        .map(|_| Ok((read_u64(reader)?, decode_phase(read_u8(reader)?)?)))
+        .collect::<io::Result<_>>()?;
+    let band_width_bps = read_optional(reader)?;
+    let band_action = match read_u8(reader)? {
+        0 => BandAction::Halt,
+        1 => BandAction::VolatilityAuction,
+        _ => return Err(invalid_data("invalid price band action")),
+    };
+    let price_band = band_width_bps.map(|width_bps| PriceBand {
+        width_bps,
+        action: band_action,
+    });
+    let instrument = read_instrument(reader)?;
+
+    // only touch the book once the whole snapshot has been read successfully
+    book.orders.clear();
+    book.stop_orders.clear();
+    book.expiries.clear();
+    for (price, order) in bids.iter().chain(&asks).flat_map(level_orders) {
+        book.orders
+            .insert(order.order_id, LevelIdentifier::new(price, order.side));
+        if let Some(expiry) = order.order_type.expiry() {
+            book.expiries.insert((expiry, order.order_id));
+        }
+    }
+    for (stop_price, order) in bid_stops.iter().chain(&ask_stops).flat_map(level_orders) {
+        book.stop_orders
+            .insert(order.order_id, LevelIdentifier::new(stop_price, order.side));
+    }
+    book.bids = bids;
+    book.asks = asks;
+    book.bid_stops = bid_stops;
+    book.ask_stops = ask_stops;
+    book.last_trade_price = last_trade_price;
+    book.market_protection = market_protection;
+    book.session_end = session_end;
+    book.self_trade_prevention = self_trade_prevention;
+    book.auction = auction;
+    book.phase = phase;
+    book.schedule = schedule;
+    book.price_band = price_band;
+    book.instrument = instrument;
+    book.market_data.set_last_sequence(market_data_sequence);
+    Ok(journal_sequence)
+}
+
+fn read_instrument(reader: &mut impl Read) -> io::Result<InstrumentSpec> {
+    let tick_table = (0..read_u64(reader)?)
+        .map(|_| Ok((read_u64(reader)?, read_u64(reader)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    if tick_table.is_empty() || tick_table.iter().any(|&(_, tick_size)| tick_size == 0) {
+        return Err(invalid_data("invalid tick table"));
+    }
+    let lot_size = read_u64(reader)?;
+    if lot_size == 0 {
+        return Err(invalid_data("invalid lot size"));
+    }
+    let mut instrument = InstrumentSpec::default()
+        .with_tick_table(tick_table)
+        .with_lot_size(lot_size);
+    if let Some(min_quantity) = read_optional(reader)? {
+        instrument = instrument.with_min_quantity(min_quantity);
+    }
+    if let Some(max_quantity) = read_optional(reader)? {
+        instrument = instrument.with_max_quantity(max_quantity);
+    }
+    Ok(instrument)
+}
+
+fn level_orders<'a>(
+    (&price, queue): (&'a u64, &'a VecDeque<Order>),
+) -> impl Iterator<Item = (u64, &'a Order)> {
+    queue.iter().map(move |order| (price, order))
+}
+
+fn write_levels(
+    writer: &mut impl Write,
+    book_side: &BTreeMap<u64, VecDeque<Order>>,
+) -> io::Result<()> {
+    write_u64(writer, book_side.len() as u64)?;
+    for (&price, queue) in book_side {
+        write_u64(writer, price)?;
+        write_u64(writer, queue.len() as u64)?;
+        for order in queue {
+            write_order(writer, order)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_levels(reader: &mut impl Read) -> io::Result<BTreeMap<u64, VecDeque<Order>>> {
+    let mut book_side = BTreeMap::new();
+    for _ in 0..read_u64(reader)? {
+        let price = read_u64(reader)?;
+        let queue = (0..read_u64(reader)?)
+            .map(|_| read_order(reader))
+            .collect::<io::Result<VecDeque<_>>>()?;
+        book_side.insert(price, queue);
+    }
+    Ok(book_side)
+}
+
+fn write_order(writer: &mut impl Write, order: &Order) -> io::Result<()> {
+    write_u64(writer, order.order_id)?;
+    let (tag, argument) = match order.order_type {
+        OrderType::FillAndKill => (0, 0),
+        OrderType::GoodTillCancel => (1, 0),
+        OrderType::FillOrKill => (2, 0),
+        OrderType::GoodForDay => (3, 0),
+        OrderType::GoodTillDate { expiry } => (4, expiry),
+        OrderType::Market => (5, 0),
+        OrderType::StopMarket { stop_price } => (6, stop_price),
+        OrderType::StopLimit { stop_price } => (7, stop_price),
+    };
+    write_u8(writer, tag)?;
+    write_u64(writer, argument)?;
+    write_u8(
+        writer,
+        match order.side {
+            Side::Bid => 0,
+            Side::Ask => 1,
+        },
+    )?;
+    write_u64(writer, order.price)?;
+    write_u64(writer, order.initial_quantity)?;
+    write_u64(writer, order.remaining_quantity)?;
+    write_optional(writer, order.display_quantity)?;
+    write_u64(writer, order.visible_quantity)?;
+    write_u8(
+        writer,
+        match order.post_only {
+            None => 0,
+            Some(PostOnly::Reject) => 1,
+            Some(PostOnly::Slide) => 2,
+        },
+    )?;
+    write_optional(writer, order.owner)
+}
+
+fn read_order(reader: &mut impl Read) -> io::Result<Order> {
+    let order_id = read_u64(reader)?;
+    let tag = read_u8(reader)?;
+    let argument = read_u64(reader)?;
+    let order_type = match tag {
+        0 => OrderType::FillAndKill,
+        1 => OrderType::GoodTillCancel,
+        2 => OrderType::FillOrKill,
+        3 => OrderType::GoodForDay,
+        4 => OrderType::GoodTillDate { expiry: argument },
+        5 => OrderType::Market,
+        6 => OrderType::StopMarket {
+            stop_price: argument,
+        },
+        7 => OrderType::StopLimit {
+            stop_price: argument,
+        },
+        _ => return Err(invalid_data("invalid order type")),
+    };
+    let side = match read_u8(reader)? {
+        0 => Side::Bid,
+        1 => Side::Ask,
+        _ => return Err(invalid_data("invalid side")),
+    };
+    let price = read_u64(reader)?;
+    let mut order = Order::new(order_type, order_id, side, price, read_u64(reader)?);
+    order.remaining_quantity = read_u64(reader)?;
+    order.display_quantity = read_optional(reader)?;
+    order.visible_quantity = read_u64(reader)?;
+    order.post_only = match read_u8(reader)? {
+        0 => None,
+        1 => Some(PostOnly::Reject),
+        2 => Some(PostOnly::Slide),
+        _ => return Err(invalid_data("invalid post-only mode")),
+    };
+    order.owner = read_optional(reader)?;
+    Ok(order)
+}
+
+fn encode_phase(phase: TradingPhase) -> u8 {
+    match phase {
+        TradingPhase::PreOpen => 0,
+        TradingPhase::OpeningAuction => 1,
+        TradingPhase::Continuous => 2,
+        TradingPhase::Halted => 3,
+        TradingPhase::ClosingAuction => 4,
+        TradingPhase::Closed => 5,
+        TradingPhase::VolatilityAuction => 6,
+    }
+}
+
+fn decode_phase(value: u8) -> io::Result<TradingPhase> {
+    match value {
+        0 => Ok(TradingPhase::PreOpen),
+        1 => Ok(TradingPhase::OpeningAuction),
+        2 => Ok(TradingPhase::Continuous),
+        3 => Ok(TradingPhase::Halted),
+        4 => Ok(TradingPhase::ClosingAuction),
+        5 => Ok(TradingPhase::Closed),
+        6 => Ok(TradingPhase::VolatilityAuction),
+        _ => Err(invalid_data("invalid trading phase")),
+    }
+}
+
+fn write_u8(writer: &mut impl Write, value: u8) -> io::Result<()> {
+    writer.write_all(&[value])
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_optional(writer: &mut impl Write, value: Option<u64>) -> io::Result<()> {
+    match value {
+        Some(value) => {
+            write_u8(writer, 1)?;
+            write_u64(writer, value)
+        }
+        None => write_u8(writer, 0),
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_optional(reader: &mut impl Read) -> io::Result<Option<u64>> {
+    match read_u8(reader)? {
+        0 => Ok(None),
+        1 => read_u64(reader).map(Some),
+        _ => Err(invalid_data("invalid optional flag")),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::ManualClock, OrderView};
+
+    fn book(clock: &ManualClock) -> Orderbook {
+        let mut book = Orderbook::with_clock(clock.clone());
+        book.set_instrument(
+            InstrumentSpec::default()
+                .with_tick_table([(0, 1), (1_000, 5)])
+                .with_lot_size(1)
+                .with_max_quantity(1_000),
+        );
+        book.set_self_trade_prevention(Some(SelfTradePrevention::CancelOldest));
+        book.set_market_protection(Some(20));
+        book.set_price_band(Some(PriceBand {
+            width_bps: 500,
+            action: BandAction::VolatilityAuction,
+        }));
+        book.set_schedule(vec![(1_000, TradingPhase::ClosingAuction)])
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 1, Side::Ask, 100, 10))
+            .unwrap();
+        book.add_order(Order::new(OrderType::GoodTillCancel, 2, Side::Bid, 100, 4))
+            .unwrap();
+        book.add_order(
+            Order::new(OrderType::GoodTillCancel, 3, Side::Ask, 101, 30)
+                .with_display_quantity(5)
+                .with_owner(7),
+        )
+        .unwrap();
+        book.add_order(Order::new(
+            OrderType::GoodTillDate { expiry: 50 },
+            4,
+            Side::Bid,
+            98,
+            8,
+        ))
+        .unwrap();
+        book.add_order(Order::new(
+            OrderType::StopLimit { stop_price: 102 },
+            5,
+            Side::Bid,
+            103,
+            2,
+        ))
+        .unwrap();
+        book
+    }
+
+    fn orders(book: &Orderbook) -> Vec<OrderView> {
+        book.side_orders(Side::Bid)
+            .chain(book.side_orders(Side::Ask))
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_restores_the_book() {
+        let clock = ManualClock::new(0);
+        let mut original = book(&clock);
+        let mut bytes = Vec::new();
+        write_snapshot(&original, 42, &mut bytes).unwrap();
+
+        let mut restored = Orderbook::with_clock(clock.clone());
+        assert_eq!(read_snapshot(&mut restored, bytes.as_slice()).unwrap(), 42);
+        assert_eq!(orders(&restored), orders(&original));
+        assert_eq!(restored.stop_order_count(), 1);
+        assert_eq!(restored.last_trade_price, Some(100));
+        assert_eq!(restored.market_protection, original.market_protection);
+        assert_eq!(
+            restored.self_trade_prevention,
+            original.self_trade_prevention
+        );
+        assert_eq!(restored.instrument(), original.instrument());
+        assert_eq!(restored.price_band, original.price_band);
+        assert_eq!(restored.schedule, original.schedule);
+
+        // both books behave the same from here on, including the stop trigger
+        let order = Order::new(OrderType::GoodTillCancel, 6, Side::Bid, 102, 12);
+        let expected = original.add_order(order.clone()).unwrap();
+        let actual = restored.add_order(order).unwrap();
+        assert_eq!(actual.trade_log, expected.trade_log);
+        assert_eq!(actual.triggered_trade_log, expected.triggered_trade_log);
+        assert_eq!(orders(&restored), orders(&original));
+        clock.set(50);
+        assert_eq!(
+            restored.expire_orders().len(),
+            original.expire_orders().len()
+        );
+    }
+
+    #[test]
+    fn foreign_and_future_snapshots_are_rejected() {
+        let mut book = book(&ManualClock::new(0));
+        let before = orders(&book);
+
+        let err = read_snapshot(&mut book, b"NOTASNAP\x01\x00".as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut bytes = Vec::new();
+        write_snapshot(&book, 1, &mut bytes).unwrap();
+        bytes[8..10].copy_from_slice(&(SNAPSHOT_VERSION + 1).to_le_bytes());
+        let err = read_snapshot(&mut book, bytes.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a truncated snapshot leaves the book untouched
+        bytes[8..10].copy_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bytes.truncate(bytes.len() - 4);
+        assert!(read_snapshot(&mut book, bytes.as_slice()).is_err());
+        assert_eq!(orders(&book), before);
+    }
+}